@@ -4,24 +4,32 @@ This demo ensure that the core is working.
 It features:
     - animation, graphics, basic keyboard input
     - persistent and volatile entities
-    - persistence (save file) in general
+    - persistence (save slots and autosave) in general
     - persistent references which can be:
         - circular (points to self)
         - pointing to elements which have despawned
+    - pausing and single frame advance
+    - rigid body physics
+    - camera zoom (mouse wheel)
+    - deterministic recording and replay of input
 */
 
 extern crate game_engine;
 use core::panic;
 use rand::prelude::*;
-use std::path::PathBuf;
+use std::{cell::Cell, path::PathBuf};
 
 use game_engine::core::{
-    GameState, MaybePersistentRef, Persistent, PersistentRef, PersistentRefPromotionResult,
-    PersistentSpawn, PersistentSpawnChanges, Volatile, VolatileSpawn, VolatileSpawnChanges, LivelinessStatus,
+    GameState, MaybePersistentRef, Persistent, PersistentGuardResult, PersistentSpawn, PersistentSpawnChanges, Volatile, VolatileSpawn, VolatileSpawnChanges, LivelinessStatus,
+    RenderInfo,
 };
+use game_engine::physics::RigidBody;
+use game_engine::render::Renderer;
+use game_engine::replay::Replay;
+use game_engine::save::{AutosavePolicy, SaveSlots};
+use game_engine::spatial::Aabb;
 
-fn central_rand(radius: f32) -> (f32, f32) {
-    let mut rng = rand::thread_rng();
+fn central_rand(rng: &mut impl Rng, radius: f32) -> (f32, f32) {
     let theta = rng.gen_range(0f32..2f32 * std::f32::consts::PI);
     (theta.cos() * radius, theta.sin() * radius)
 }
@@ -34,99 +42,91 @@ struct PrimarySquare {
     b: bool,
 
     // coords origin is center of screen, throughout this example
-    x: f32,
-    y: f32,
-    dx: f32,
-    dy: f32,
+    body: RigidBody,
 
     // starts at 1 on spawn and fades up to max.
     // used for gradual increase on spawn
     fade_in_alpha: u8,
 
+    // random choices are made in generate_rate, since that's where the game
+    // state's rng is available. they are used in apply_spawns
     #[serde(skip)]
-    x_rate: f32,
-    #[serde(skip)]
-    y_rate: f32,
-    #[serde(skip)]
-    dx_rate: f32,
-    #[serde(skip)]
-    dy_rate: f32,
+    replacement: Cell<Option<Box<PrimarySquare>>>,
     #[serde(skip)]
-    d_dampener: f32,
+    tail_drift: (f32, f32),
 }
 
 impl PrimarySquare {
     const SIZE: f32 = 20.;
     const REPLACE_CHANCE: f64 = 0.0005;
-    fn new() -> Self {
-        let (x, y) = central_rand(200f32);
+    // initial orbital speed, per second
+    const SPEED: f32 = 120.;
+    // strength of the pull towards the center
+    const ATTRACTION: f32 = 14400.;
+    fn new(rng: &mut impl Rng) -> Self {
+        let (x, y) = central_rand(rng, 200f32);
         let dist = (x.powi(2) + y.powi(2)).sqrt();
-        let (dx, dy) = (-y / dist, x / dist);
-        let mut rng = rand::thread_rng();
+        let (vx, vy) = (-y / dist * Self::SPEED, x / dist * Self::SPEED);
         PrimarySquare {
             r: rng.gen_bool(0.5),
             g: rng.gen_bool(0.5),
             b: rng.gen_bool(0.5),
-            x,
-            y,
-            dx,
-            dy,
+            body: RigidBody::new(x, y).with_velocity(vx, vy),
             fade_in_alpha: 1,
-            x_rate: 0f32,
-            y_rate: 0f32,
-            dx_rate: 0f32,
-            dy_rate: 0f32,
-            d_dampener: 1f32,
+            replacement: Cell::new(None),
+            tail_drift: (0f32, 0f32),
         }
     }
 }
 
 #[typetag::serde]
 impl Persistent for PrimarySquare {
-    fn generate_rate(&mut self, _state: &GameState) {
-        self.x_rate = self.dx;
-        self.y_rate = self.dy;
+    fn generate_rate(&mut self, state: &GameState) {
+        let mut rng = state.rng();
+        if rng.gen_bool(Self::REPLACE_CHANCE) {
+            self.replacement.set(Some(Box::new(PrimarySquare::new(&mut *rng))));
+        }
+        self.tail_drift = central_rand(&mut *rng, 0.2f32);
 
-        let mut r = (self.x.powi(2) + self.y.powi(2)).sqrt();
+        let (x, y) = (self.body.x, self.body.y);
+        let mut r = (x.powi(2) + y.powi(2)).sqrt();
         r = r.max(5f32);
 
-        // accelerate based on inverse square of distance
-        self.dx_rate = -self.x / r.powi(2);
-        self.dy_rate = -self.y / r.powi(2);
+        // accelerate based on inverse square of distance. the body is
+        // integrated by the engine after apply_rate
+        self.body.apply_force(
+            -x / r.powi(2) * Self::ATTRACTION,
+            -y / r.powi(2) * Self::ATTRACTION,
+        );
 
-        // there's some instability which causes it to fling off into infinite
-        // over time. to prevent this, a speed dampener is applied based on distance
+        // keep squares that drift far away from flinging off into infinity
         let r_div = (2f32 * 700f32.powi(2)).sqrt();
-        self.d_dampener = 1f32 - r / (r_div) * 0.01;
+        self.body.damping = r / r_div * 1.2;
 
-        // the dampener reduces all velocity (inclusing rotational component).
+        // the damping reduces all velocity (inclusing rotational component).
         // adding a tiny amount to keep things spinning spin in opposite
         // direction over time
-        let (x_h, y_h) = (self.x / r, self.y / r);
-        self.dx_rate += -y_h * 0.0002;
-        self.dy_rate += x_h * 0.0002;
+        let (x_h, y_h) = (x / r, y / r);
+        self.body.apply_force(-y_h * 2.88, x_h * 2.88);
     }
 
     fn apply_rate(&mut self) {
         if self.fade_in_alpha != u8::MAX {
             self.fade_in_alpha += 1;
         }
+    }
 
-        self.x += self.x_rate;
-        self.dx += self.dx_rate;
-        self.y += self.y_rate;
-        self.dy += self.dy_rate;
-
-        self.dx *= self.d_dampener;
-        self.dy *= self.d_dampener;
+    fn rigid_body(&mut self) -> Option<&mut RigidBody> {
+        Some(&mut self.body)
     }
 
     fn apply_spawns(&self) -> PersistentSpawnChanges {
-        let replace_self: bool = rand::thread_rng().gen_bool(Self::REPLACE_CHANCE);
+        let replacement = self.replacement.take();
+        let replace_self = replacement.is_some();
 
         let mut persistent_spawns: Vec<(&'static str, Vec<PersistentSpawn>)> = Vec::new();
-        if replace_self {
-            persistent_spawns.push((OBJECTS, vec![Box::new(PrimarySquare::new())]))
+        if let Some(replacement) = replacement {
+            persistent_spawns.push((OBJECTS, vec![replacement]))
         }
 
         let mut volatile_spawns: Vec<(&'static str, Vec<VolatileSpawn>)> = Vec::new();
@@ -139,7 +139,7 @@ impl Persistent for PrimarySquare {
     }
 
     /// draw to the screen
-    fn render(&self, _canvas: &mut sdl2::render::WindowCanvas) {
+    fn render(&self, _renderer: &mut dyn Renderer, _info: &RenderInfo) {
         // this entity drawn entirely from particle effects it emitts
     }
 }
@@ -163,12 +163,14 @@ struct PrimarySquareTail {
 
 impl PrimarySquareTail {
     fn new(from: &PrimarySquare) -> Self {
-        let (drift_x, drift_y) = central_rand(0.2f32);
+        let (drift_x, drift_y) = from.tail_drift;
+        // the tail moves per tick, the same distance as the square last moved
+        let (dx, dy) = from.body.displacement();
         Self {
-            x: from.x,
-            y: from.y,
-            dx: from.dx + drift_x,
-            dy: from.dy + drift_y,
+            x: from.body.x,
+            y: from.body.y,
+            dx: dx + drift_x,
+            dy: dy + drift_y,
             x_rate: 0f32,
             y_rate: 0f32,
             dx_rate: 0f32,
@@ -182,12 +184,12 @@ impl PrimarySquareTail {
 }
 
 impl Volatile for PrimarySquareTail {
-    fn generate_rate(&mut self, _state: &GameState) {
+    fn generate_rate(&mut self, state: &GameState) {
         self.x_rate = self.dx;
         self.y_rate = self.dy;
         // deviate more and more as the particles expire
         let progress = (self.alpha) as f32 / 255f32;
-        let (drift_x, drift_y) = central_rand(0.1f32 * (1f32 - progress));
+        let (drift_x, drift_y) = central_rand(&mut *state.rng(), 0.1f32 * (1f32 - progress));
         self.dx_rate = drift_x;
         self.dy_rate = drift_y;
     }
@@ -207,8 +209,10 @@ impl Volatile for PrimarySquareTail {
         }
     }
 
-    fn render(&self, canvas: &mut sdl2::render::WindowCanvas) {
-        let window_size = canvas.output_size().unwrap();
+    fn render(&self, renderer: &mut dyn Renderer, info: &RenderInfo) {
+        // position between the previous tick and this one
+        let x = self.x - self.x_rate * (1f32 - info.alpha);
+        let y = self.y - self.y_rate * (1f32 - info.alpha);
         let progress_on = (self.alpha) as f32 / 255f32; // from 1 (inclusive) to 0 (exclusive)
         let progress_off = 1f32 - progress_on;
 
@@ -217,14 +221,9 @@ impl Volatile for PrimarySquareTail {
         let green = (255f32 * if self.g { progress_on } else { progress_off }) as u8;
         let blue = (255f32 * if self.b { progress_on } else { progress_off }) as u8;
         let alpha = (100f32 * progress_on) as u8;
-        canvas.set_draw_color(sdl2::pixels::Color::RGBA(red, green, blue, alpha));
-        canvas
-            .fill_rect(sdl2::rect::Rect::new(
-                (self.x - size / 2.) as i32 + window_size.0 as i32 / 2,
-                (self.y - size / 2.) as i32 + window_size.1 as i32 / 2,
-                size as u32,
-                size as u32,
-            ))
+        renderer.set_draw_color(sdl2::pixels::Color::RGBA(red, green, blue, alpha));
+        renderer
+            .fill_world_rect(Aabb::centered(x, y, size, size))
             .unwrap();
     }
 }
@@ -239,22 +238,23 @@ struct Follower {
     x: f32,
     y: f32,
     speed: f32,
-    countdown: usize,
+    // the tick at which a new followee is chosen
+    retarget_tick: u64,
 }
 
 impl Follower {
     const SIZE: f32 = 2.;
     const MIN_SPEED: f32 = 2.;
     const MAX_SPEED_EXCLUSIVE: f32 = 10.;
-    const COUNTDOWN_RESET: usize = 5 * GameState::GOAL_FPS as usize;
+    const RETARGET_TICKS: u64 = 5 * GameState::DEFAULT_TICK_RATE as u64;
 
-    fn new() -> Self {
+    fn new(current_tick: u64, rng: &mut impl Rng) -> Self {
         Self {
             followee: MaybePersistentRef::None,
             x: 0f32,
             y: 0f32,
-            speed: rand::thread_rng().gen_range(Self::MIN_SPEED..Self::MAX_SPEED_EXCLUSIVE),
-            countdown: rand::thread_rng().gen_range(0..Self::COUNTDOWN_RESET),
+            speed: rng.gen_range(Self::MIN_SPEED..Self::MAX_SPEED_EXCLUSIVE),
+            retarget_tick: current_tick + rng.gen_range(0..Self::RETARGET_TICKS),
         }
     }
 
@@ -267,36 +267,26 @@ impl Follower {
             // followee. this won't happen since in this case self is always in
             // that layer, making it never empty.
             MaybePersistentRef::None => return (self.x, self.y),
-            // followee despawned. hold in place until countdown runs out
+            // followee despawned. hold in place until retarget
             MaybePersistentRef::Despawned => return (self.x, self.y),
             MaybePersistentRef::Some(e) => e,
         };
 
-        let (e_position, e) = match weak_ref.get() {
-            PersistentRefPromotionResult::Despawned => return (self.x, self.y),
-            // self reference. go to origin
-            PersistentRefPromotionResult::Taken => return (self.x, self.y),
-            PersistentRefPromotionResult::Some(e_position, e) => (e_position, e),
+        let e = match weak_ref.guard() {
+            PersistentGuardResult::Some(e) => e,
+            PersistentGuardResult::Despawned => return (self.x, self.y),
+            // self reference. hold in place
+            PersistentGuardResult::Taken => return (self.x, self.y),
         };
 
-        // downcast to concrete type and get position
-        let e = match e.downcast::<Follower>() {
-            Ok(e) => {
-                let ret = (e.x, e.y);
-                // return the entitiy back into its position
-                PersistentRef::set((e_position, e));
-                return ret;
-            }
-            Err(e) => e,
-        };
-        let _e = match e.downcast::<PrimarySquare>() {
-            Ok(e) => {
-                let ret = (e.x, e.y);
-                PersistentRef::set((e_position, e));
-                return ret;
-            }
-            Err(e) => e,
-        };
+        // downcast to concrete type and get position. the entity is returned
+        // back into its position when the guard is dropped
+        if let Some(e) = e.downcast_ref::<Follower>() {
+            return (e.x, e.y);
+        }
+        if let Some(e) = e.downcast_ref::<PrimarySquare>() {
+            return (e.body.x, e.body.y);
+        }
         panic!("get_follow_pos not implemented for followee type");
     }
 }
@@ -318,9 +308,10 @@ impl Persistent for Follower {
     fn generate_rate(&mut self, state: &GameState) {
         let mut needs_new_followee = false;
 
-        if self.countdown == 0 {
+        if state.tick() >= self.retarget_tick {
             // periodically get new follower
             needs_new_followee = true;
+            self.retarget_tick = state.tick() + Self::RETARGET_TICKS;
         } else if let MaybePersistentRef::None = self.followee {
             needs_new_followee = true;
         } else if let MaybePersistentRef::Despawned = self.followee {
@@ -341,7 +332,7 @@ impl Persistent for Follower {
 
         // don't stay on the same entity more than once. loop until new one found
         loop {
-            let random_index = rand::thread_rng().gen_range(0..entities.len());
+            let random_index = state.rng().gen_range(0..entities.len());
             let random_entity = &entities[random_index];
 
             // should the new one be used? or should it do another
@@ -355,37 +346,23 @@ impl Persistent for Follower {
                         // impossible to find different followee
                         true
                     } else {
-                        // check if this followee is different that the current one
-                        match persistent_ref.0.upgrade() {
-                            Some(e) => {
-                                // only condition in which another iteration
-                                // happens is when entities.len() > 1 and the
-                                // randomly selected entitiy is the same as the
-                                // followee
-                                !std::rc::Rc::ptr_eq(&e, &random_entity.0)
-                            }
-                            None => true,
-                        }
+                        // check if this followee is different that the current
+                        // one. only condition in which another iteration
+                        // happens is when entities.len() > 1 and the randomly
+                        // selected entitiy is the same as the followee
+                        persistent_ref.id() != Some(random_entity.id())
                     }
                 }
             };
 
             if use_random_entity {
-                let weak = std::rc::Rc::downgrade(&random_entity.0);
-                self.followee = MaybePersistentRef::Some(PersistentRef(weak));
+                self.followee = MaybePersistentRef::Some(random_entity.downgrade());
                 break;
             }
         }
     }
 
     fn apply_rate(&mut self) {
-        if self.countdown == 0 {
-            self.countdown = Self::COUNTDOWN_RESET;
-        } else {
-            // each follow occurs for Self::COUNTDOWN_RESET downto 1 frames (inclusive)
-            self.countdown -= 1;
-        }
-
         let (goal_x, goal_y) = Follower::get_follow_pos(&self);
         let mag = ((goal_x - self.x).powi(2) + (goal_y - self.y).powi(2)).sqrt();
         if mag < self.speed {
@@ -400,15 +377,14 @@ impl Persistent for Follower {
         }
     }
 
-    fn render(&self, canvas: &mut sdl2::render::WindowCanvas) {
-        let window_size = canvas.output_size().unwrap();
-        canvas.set_draw_color(sdl2::pixels::Color::RGBA(255, 255, 255, 50));
-        canvas
-            .fill_rect(sdl2::rect::Rect::new(
-                (self.x - Follower::SIZE / 2.) as i32 + window_size.0 as i32 / 2,
-                (self.y - Follower::SIZE / 2.) as i32 + window_size.1 as i32 / 2,
-                Follower::SIZE as u32,
-                Follower::SIZE as u32,
+    fn render(&self, renderer: &mut dyn Renderer, _info: &RenderInfo) {
+        renderer.set_draw_color(sdl2::pixels::Color::RGBA(255, 255, 255, 50));
+        renderer
+            .fill_world_rect(Aabb::centered(
+                self.x,
+                self.y,
+                Follower::SIZE,
+                Follower::SIZE,
             ))
             .unwrap();
     }
@@ -416,36 +392,58 @@ impl Persistent for Follower {
 
 // =================================================================================================
 
-fn get_save_path() -> String {
-    let mut save_path: PathBuf = file!().into();
-    save_path.pop();
-    save_path.push("0_hello_save_file.save");
-    save_path.to_str().unwrap().to_owned()
+fn get_save_dir() -> PathBuf {
+    let mut save_dir: PathBuf = file!().into();
+    save_dir.pop();
+    save_dir.push("0_hello_saves");
+    save_dir
+}
+
+fn get_replay_path() -> String {
+    let mut replay_path: PathBuf = file!().into();
+    replay_path.pop();
+    replay_path.push("0_hello_replay_file.replay");
+    replay_path.to_str().unwrap().to_owned()
 }
 
 const OBJECTS: &'static str = "objects";
 const RENDER_ORDER: &'static [&'static str] = &[OBJECTS];
 
 fn main() -> Result<(), String> {
-    let save_file_path: String = get_save_path();
+    let slots = SaveSlots::in_dir(get_save_dir())?;
+    let replay_file_path: String = get_replay_path();
 
     fn populate_initial_entities(state: &mut GameState) {
         for _ in 0..700 {
-            state.spawn_persistent(Box::new(Follower::new()), OBJECTS);
+            let follower = Follower::new(state.tick(), &mut *state.rng());
+            state.spawn_persistent(Box::new(follower), OBJECTS);
         }
         for _ in 0..5 {
-            state.spawn_persistent(Box::new(PrimarySquare::new()), OBJECTS);
+            let square = PrimarySquare::new(&mut *state.rng());
+            state.spawn_persistent(Box::new(square), OBJECTS);
         }
     }
 
-    let mut state = GameState::new("controls: s, l, r, esc", (800u32, 600u32), RENDER_ORDER)?;
-    // check if save file already exists
-    if std::fs::metadata(save_file_path.clone()).is_ok() {
-        println!("loading save");
-        state.load(save_file_path.clone())?;
-    } else {
+    let mut state = GameState::new("controls: s, l, r, p, n, c, v, esc", (800u32, 600u32), RENDER_ORDER)?;
+    // continue from the most recent save, manual or automatic. if it can't be
+    // loaded, try the next most recent, and otherwise start fresh
+    let mut loaded_any = false;
+    for slot in slots.list()? {
+        match state.load_slot(&slots, &slot.name) {
+            Ok(loaded) => {
+                println!("loaded {}", loaded.display());
+                loaded_any = true;
+                break;
+            }
+            Err(e) => println!("couldn't load {}: {}", slot.name, e),
+        }
+    }
+    if !loaded_any {
         populate_initial_entities(&mut state);
     }
+    state.enable_autosave(slots.clone(), AutosavePolicy::default(), |e| {
+        println!("autosave failed: {}", e)
+    })?;
     state.run(
         |state, event| {
             match event {
@@ -458,16 +456,19 @@ fn main() -> Result<(), String> {
                     keycode: Some(sdl2::keyboard::Keycode::S),
                     ..
                 } => {
-                    state.save(save_file_path.clone())?;
+                    let screenshot = state.screenshot().ok();
+                    state.save_slot(&slots, "manual", "manual save".to_owned(), screenshot.as_deref())?;
                     println!("manual save");
                 }
                 sdl2::event::Event::KeyUp {
                     keycode: Some(sdl2::keyboard::Keycode::L),
                     ..
                 } => {
-                    if std::fs::metadata(save_file_path.clone()).is_ok() {
-                        state.load(save_file_path.clone())?;
-                        println!("manual load");
+                    if slots.exists("manual") {
+                        match state.load_slot(&slots, "manual") {
+                            Ok(loaded) => println!("manual load from {}", loaded.display()),
+                            Err(e) => println!("manual load failed: {}", e),
+                        }
                     }
                 }
                 sdl2::event::Event::KeyUp {
@@ -478,13 +479,55 @@ fn main() -> Result<(), String> {
                     populate_initial_entities(state);
                     println!("reset");
                 }
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(sdl2::keyboard::Keycode::P),
+                    ..
+                } => {
+                    if state.is_paused() {
+                        state.resume();
+                        println!("resume");
+                    } else {
+                        state.pause();
+                        println!("pause");
+                    }
+                }
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(sdl2::keyboard::Keycode::N),
+                    ..
+                } => {
+                    // single frame advance while paused
+                    state.advance(1);
+                }
+                // the key press which stopped the recording is itself
+                // recorded. ignore it when it comes back in the replay
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(sdl2::keyboard::Keycode::C),
+                    ..
+                } if !state.is_replaying() => {
+                    if let Some(replay) = state.stop_recording() {
+                        replay.save(replay_file_path.clone())?;
+                        println!("recording saved");
+                    } else {
+                        state.start_recording()?;
+                        println!("recording");
+                    }
+                }
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(sdl2::keyboard::Keycode::V),
+                    ..
+                } if std::fs::metadata(replay_file_path.clone()).is_ok() => {
+                    state.start_replay(Replay::load(replay_file_path.clone())?)?;
+                    println!("replaying");
+                }
+                sdl2::event::Event::MouseWheel { y, .. } => {
+                    state.camera_mut().zoom *= 1.1f32.powi(*y);
+                }
                 _ => {}
             }
             Ok(true)
         },
-        |_| {},
+        |_| Ok(()),
     )?;
-    println!("save on exit");
-    state.save(save_file_path)?;
+    // run autosaved on exit
     Ok(())
 }
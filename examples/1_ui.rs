@@ -333,10 +333,9 @@ fn character_selected_menu<'sdl>(
 
 fn main() -> Result<(), String> {
     let mut state = GameState::new("ui with layers of buttons", (400u32, 600u32), &[])?;
-    let texture_creator = state.canvas.texture_creator();
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
 
-    let mut ui = UI::new(&state.canvas, &ttf_context, &texture_creator)?;
+    let mut ui = UI::new(&state.canvas, &ttf_context)?;
     ui.add(initial_menu());
 
     let ui_cell = Cell::new(Option::Some(ui));
@@ -352,10 +351,11 @@ fn main() -> Result<(), String> {
             ui_cell.set(Some(ui));
             Ok(ret)
         },
-        |canvas| {
+        |renderer| {
             let ui = ui_cell.take().unwrap();
-            ui.render(canvas);
+            let result = ui.render(renderer);
             ui_cell.set(Some(ui));
+            result
         },
     )?;
     Ok(())
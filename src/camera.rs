@@ -0,0 +1,163 @@
+use crate::spatial::Aabb;
+
+/// a view of the world, drawn to part of the window. see `GameState::cameras`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Camera {
+    /// the world point at the center of the viewport
+    pub x: f32,
+    pub y: f32,
+    /// screen pixels per world unit
+    pub zoom: f32,
+    /// radians, counter clockwise. this rotates where things are drawn, but
+    /// entities are responsible for rotating what they draw
+    pub rotation: f32,
+    /// if set, the camera is kept in these world bounds so that nothing
+    /// outside them is shown (if the bounds are smaller than the view, it's
+    /// centered on them instead). rotation is ignored
+    pub bounds: Option<Aabb>,
+    /// the area of the window drawn to, as proportions of the window size
+    /// from 0 to 1. e.g. the left half for two player split screen
+    pub viewport: Aabb,
+}
+
+impl Default for Camera {
+    /// centered on the world origin and covering the whole window
+    fn default() -> Self {
+        Self {
+            x: 0f32,
+            y: 0f32,
+            zoom: 1f32,
+            rotation: 0f32,
+            bounds: None,
+            viewport: Aabb::new(0f32, 0f32, 1f32, 1f32),
+        }
+    }
+}
+
+impl Camera {
+    pub fn new(viewport: Aabb) -> Self {
+        Self {
+            viewport,
+            ..Default::default()
+        }
+    }
+
+    /// move part of the way towards a target. amount is from 0 (don't move)
+    /// to 1 (snap to the target); call it each tick for smooth following
+    pub fn follow(&mut self, target: (f32, f32), amount: f32) {
+        self.x += (target.0 - self.x) * amount;
+        self.y += (target.1 - self.y) * amount;
+    }
+
+    /// the viewport in pixels, for a window of this size
+    pub fn viewport_rect(&self, window_size: (u32, u32)) -> sdl2::rect::Rect {
+        let (w, h) = (window_size.0 as f32, window_size.1 as f32);
+        sdl2::rect::Rect::new(
+            (self.viewport.x * w) as i32,
+            (self.viewport.y * h) as i32,
+            (self.viewport.w * w).max(1f32) as u32,
+            (self.viewport.h * h).max(1f32) as u32,
+        )
+    }
+
+    /// the world to screen transform for a layer with this parallax. see
+    /// `LayerSettings::parallax`
+    pub fn transform(&self, window_size: (u32, u32), parallax: f32) -> Transform {
+        let viewport = self.viewport_rect(window_size);
+        let (mut x, mut y) = (self.x, self.y);
+        if let Some(bounds) = self.bounds {
+            let half_w = viewport.width() as f32 / 2f32 / self.zoom;
+            let half_h = viewport.height() as f32 / 2f32 / self.zoom;
+            x = clamp_or_center(x, bounds.x + half_w, bounds.x + bounds.w - half_w);
+            y = clamp_or_center(y, bounds.y + half_h, bounds.y + bounds.h - half_h);
+        }
+        Transform {
+            center: (x * parallax, y * parallax),
+            zoom: self.zoom,
+            rotation: self.rotation,
+            viewport,
+        }
+    }
+}
+
+// the midpoint if the range is empty
+fn clamp_or_center(v: f32, min: f32, max: f32) -> f32 {
+    if min > max {
+        (min + max) / 2f32
+    } else {
+        v.clamp(min, max)
+    }
+}
+
+/// converts between world coordinates and screen coordinates, which are
+/// pixels relative to the top left of the viewport being drawn to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    /// world point at the center of the viewport
+    pub center: (f32, f32),
+    pub zoom: f32,
+    pub rotation: f32,
+    /// in pixels, relative to the window
+    pub viewport: sdl2::rect::Rect,
+}
+
+impl Transform {
+    /// screen coordinates are world coordinates. for layers which aren't
+    /// drawn through a camera, e.g. the HUD
+    pub fn identity(window_size: (u32, u32)) -> Self {
+        Self {
+            center: (window_size.0 as f32 / 2f32, window_size.1 as f32 / 2f32),
+            zoom: 1f32,
+            rotation: 0f32,
+            viewport: sdl2::rect::Rect::new(0, 0, window_size.0.max(1), window_size.1.max(1)),
+        }
+    }
+
+    pub fn world_to_screen(&self, point: (f32, f32)) -> (f32, f32) {
+        let (dx, dy) = (point.0 - self.center.0, point.1 - self.center.1);
+        let (sin, cos) = (-self.rotation).sin_cos();
+        let (rx, ry) = (dx * cos - dy * sin, dx * sin + dy * cos);
+        (
+            rx * self.zoom + self.viewport.width() as f32 / 2f32,
+            ry * self.zoom + self.viewport.height() as f32 / 2f32,
+        )
+    }
+
+    /// e.g. for mouse events. the point must be relative to the viewport; use
+    /// window_to_world for window coordinates
+    pub fn screen_to_world(&self, point: (f32, f32)) -> (f32, f32) {
+        let rx = (point.0 - self.viewport.width() as f32 / 2f32) / self.zoom;
+        let ry = (point.1 - self.viewport.height() as f32 / 2f32) / self.zoom;
+        let (sin, cos) = self.rotation.sin_cos();
+        (
+            rx * cos - ry * sin + self.center.0,
+            rx * sin + ry * cos + self.center.1,
+        )
+    }
+
+    /// world point under a point in window coordinates
+    pub fn window_to_world(&self, point: (f32, f32)) -> (f32, f32) {
+        self.screen_to_world((
+            point.0 - self.viewport.x() as f32,
+            point.1 - self.viewport.y() as f32,
+        ))
+    }
+
+    /// size on screen of a world distance
+    pub fn scale(&self, length: f32) -> f32 {
+        length * self.zoom
+    }
+
+    /// screen rect of a world box, centered on where the box's center is
+    /// drawn. the size is unaffected by rotation
+    pub fn world_rect(&self, bounds: Aabb) -> sdl2::rect::Rect {
+        let (cx, cy) = self.world_to_screen(bounds.center());
+        let (w, h) = (self.scale(bounds.w), self.scale(bounds.h));
+        sdl2::rect::Rect::new(
+            (cx - w / 2f32) as i32,
+            (cy - h / 2f32) as i32,
+            w.max(0f32) as u32,
+            h.max(0f32) as u32,
+        )
+    }
+}
@@ -0,0 +1,150 @@
+use crate::spatial::Aabb;
+
+/// the area an entity collides with, in world coordinates
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Shape {
+    Aabb(Aabb),
+    Circle { x: f32, y: f32, radius: f32 },
+}
+
+impl Shape {
+    /// smallest box containing the shape
+    pub fn bounds(&self) -> Aabb {
+        match *self {
+            Shape::Aabb(aabb) => aabb,
+            Shape::Circle { x, y, radius } => Aabb::centered(x, y, radius * 2f32, radius * 2f32),
+        }
+    }
+
+    /// inclusive of the edges, so shapes which touch overlap
+    pub fn overlaps(&self, other: &Shape) -> bool {
+        match (*self, *other) {
+            (Shape::Aabb(a), Shape::Aabb(b)) => a.intersects(&b),
+            (Shape::Aabb(a), Shape::Circle { x, y, radius })
+            | (Shape::Circle { x, y, radius }, Shape::Aabb(a)) => {
+                a.distance_squared((x, y)) <= radius * radius
+            }
+            (
+                Shape::Circle {
+                    x: x0,
+                    y: y0,
+                    radius: r0,
+                },
+                Shape::Circle {
+                    x: x1,
+                    y: y1,
+                    radius: r1,
+                },
+            ) => {
+                let dx = x1 - x0;
+                let dy = y1 - y0;
+                dx * dx + dy * dy <= (r0 + r1) * (r0 + r1)
+            }
+        }
+    }
+}
+
+/// what an entity collides as, and what it collides with.\
+/// layer is the set of collision layers (bits) this is in, and mask is the set
+/// of collision layers it detects. an entity is only told about overlaps with
+/// colliders whose layer intersects its own mask
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Collider {
+    pub shape: Shape,
+    pub layer: u32,
+    pub mask: u32,
+}
+
+impl Collider {
+    /// in every collision layer and detects every collision layer
+    pub fn new(shape: Shape) -> Self {
+        Self {
+            shape,
+            layer: u32::MAX,
+            mask: u32::MAX,
+        }
+    }
+
+    pub fn with_layer(mut self, layer: u32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn with_mask(mut self, mask: u32) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    /// true if this is told about overlaps with other
+    pub fn detects(&self, other: &Collider) -> bool {
+        self.mask & other.layer != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollisionPhase {
+    /// the colliders started overlapping this tick
+    Enter,
+    /// the colliders were overlapping last tick too
+    Stay,
+    /// the colliders overlapped last tick but no longer do, or the other
+    /// entity despawned
+    Exit,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circle(x: f32, y: f32, radius: f32) -> Shape {
+        Shape::Circle { x, y, radius }
+    }
+
+    fn aabb(x: f32, y: f32, w: f32, h: f32) -> Shape {
+        Shape::Aabb(Aabb::new(x, y, w, h))
+    }
+
+    #[test]
+    fn circle_bounds() {
+        assert_eq!(
+            circle(5f32, 5f32, 2f32).bounds(),
+            Aabb::new(3f32, 3f32, 4f32, 4f32)
+        );
+    }
+
+    #[test]
+    fn overlaps_is_symmetric_and_inclusive() {
+        let pairs = [
+            (
+                aabb(0f32, 0f32, 2f32, 2f32),
+                aabb(2f32, 2f32, 1f32, 1f32),
+                true,
+            ),
+            (
+                aabb(0f32, 0f32, 2f32, 2f32),
+                aabb(2.1, 0f32, 1f32, 1f32),
+                false,
+            ),
+            (circle(0f32, 0f32, 1f32), circle(2f32, 0f32, 1f32), true),
+            (circle(0f32, 0f32, 1f32), circle(2.1, 0f32, 1f32), false),
+            // near the box's corner, but not touching it
+            (aabb(0f32, 0f32, 2f32, 2f32), circle(3f32, 3f32, 1.2), false),
+            (aabb(0f32, 0f32, 2f32, 2f32), circle(3f32, 1f32, 1f32), true),
+        ];
+        for (a, b, expected) in pairs {
+            assert_eq!(a.overlaps(&b), expected, "{:?} {:?}", a, b);
+            assert_eq!(b.overlaps(&a), expected, "{:?} {:?}", b, a);
+        }
+    }
+
+    #[test]
+    fn detects_uses_own_mask_and_other_layer() {
+        let shape = circle(0f32, 0f32, 1f32);
+        let player = Collider::new(shape).with_layer(0b01).with_mask(0b10);
+        let pickup = Collider::new(shape).with_layer(0b10).with_mask(0);
+        assert!(player.detects(&pickup));
+        assert!(!pickup.detects(&player));
+        assert!(!player.detects(&player));
+        assert!(Collider::new(shape).detects(&pickup));
+    }
+}
@@ -1,14 +1,32 @@
 use core::panic;
 use downcast_rs::{impl_downcast, Downcast};
-use sdl2::render::WindowCanvas;
+use rand::SeedableRng;
 use serde::ser::{SerializeMap, SerializeStruct};
 use std::{
-    cell::Cell,
-    collections::{BTreeMap, HashMap, HashSet},
+    any::{Any, TypeId},
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
     rc::Rc,
     rc::Weak,
 };
 
+use crate::camera::{Camera, Transform};
+use crate::collision::{Collider, CollisionPhase};
+use crate::message::{Message, Recipient};
+use crate::physics::{self, RigidBody};
+use crate::render::{Renderer, WindowRenderer};
+use crate::replay::{RecordedEvent, Replay, ReplaySettings};
+use crate::save::{
+    self, autosave::Autosaver, AutosavePolicy, SaveEnvelope, SaveFormat, SaveMetadata, SaveSchema,
+    SaveSlots,
+};
+use crate::spatial::{Aabb, SpatialHash};
+use sdl2::{
+    pixels::PixelFormatEnum,
+    surface::{Surface, SurfaceRef},
+};
+
 extern crate sdl2;
 
 /// signaling for entity despawn
@@ -36,6 +54,19 @@ pub struct VolatileSpawnChanges {
     pub volatile_spawns: Vec<(&'static str, Vec<VolatileSpawn>)>,
 }
 
+/// passed to entities when they are drawn
+pub struct RenderInfo {
+    /// how far between the previous tick and the next one this frame is drawn,
+    /// from 0 (inclusive) to 1 (exclusive). the simulation runs at a fixed tick
+    /// rate while rendering happens as fast as possible, so an entity can draw
+    /// itself at `previous + (current - previous) * alpha` to appear smooth
+    pub alpha: f32,
+    /// from world coordinates to the renderer. this is also the renderer's
+    /// transform. the renderer's viewport is set to that of the camera being
+    /// drawn, so a layer is rendered once per camera
+    pub transform: Transform,
+}
+
 /// anything which is part of the game loop and is not saved. e.g. particle effect.
 pub trait Volatile: Downcast {
     /// second thing to happen per frame (preceded by sdl event handling)\
@@ -58,7 +89,65 @@ pub trait Volatile: Downcast {
 
     /// last thing to happen per frame\
     /// draw to the screen\
-    fn render(&self, canvas: &mut WindowCanvas);
+    fn render(&self, renderer: &mut dyn Renderer, info: &RenderInfo);
+
+    /// where this is in the world. entities that return Some are put in the
+    /// spatial index, if it's enabled. see `GameState::enable_spatial_index`
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// the shape used for collisions. entities that return Some are checked
+    /// against each other once per step, after generate_rate
+    fn collider(&self) -> Option<Collider> {
+        None
+    }
+
+    /// happens after generate_rate and before apply_rate, once for each
+    /// collider that this overlaps (or stopped overlapping) and detects.
+    /// not called for entities in paused layers
+    fn on_collision(&mut self, _state: &GameState, _collision: &Collision) {}
+
+    /// physics state which the engine integrates after apply_rate, and
+    /// bounces off other bodies that this collides with
+    fn rigid_body(&mut self) -> Option<&mut RigidBody> {
+        None
+    }
+
+    /// happens before generate_rate\
+    /// receive a message posted during the previous frame. see
+    /// `GameState::post`
+    fn on_message(&mut self, _state: &GameState, _message: &Message) {}
+
+    /// true if this receives messages posted to the channel
+    fn subscribed(&self, _channel: &str) -> bool {
+        false
+    }
+
+    /// draw order within the layer, lowest first, e.g. y position for top
+    /// down depth. ties are drawn in spawn order, with volatiles before
+    /// persistents. see `LayerSettings::sorted`
+    fn sort_key(&self) -> f32 {
+        0f32
+    }
+
+    /// happens once this is added to the game state, either by a spawn
+    /// function or by being spawned from apply_spawns
+    fn on_spawn(&mut self, _state: &GameState) {}
+
+    /// happens once this is removed from the game state, either by returning
+    /// Despawn from apply_spawns or by a clear function. the returned spawns
+    /// (e.g. a death effect) are spawned as if from apply_spawns, except that
+    /// they are dropped when despawned by a clear function
+    fn on_despawn(&mut self, _state: &GameState) -> Vec<(&'static str, Vec<VolatileSpawn>)> {
+        Vec::new()
+    }
+
+    /// happens after a save file is loaded or a replay is started, once every
+    /// persistent entity and reference is in place.\
+    /// intended for rebuilding caches. it shouldn't use the rng or post
+    /// messages, since replays call it where recording didn't
+    fn on_loaded(&mut self, _state: &GameState) {}
 }
 impl_downcast!(Volatile);
 
@@ -86,7 +175,52 @@ pub trait Persistent: Downcast {
 
     /// last thing to happen per frame\
     /// draw to the screen\
-    fn render(&self, canvas: &mut WindowCanvas);
+    fn render(&self, renderer: &mut dyn Renderer, info: &RenderInfo);
+
+    /// same as `Volatile::bounds`
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// same as `Volatile::collider`
+    fn collider(&self) -> Option<Collider> {
+        None
+    }
+
+    /// same as `Volatile::on_collision`
+    fn on_collision(&mut self, _state: &GameState, _collision: &Collision) {}
+
+    /// same as `Volatile::rigid_body`. the body is saved as part of the entity
+    /// (it should be a serialized field)
+    fn rigid_body(&mut self) -> Option<&mut RigidBody> {
+        None
+    }
+
+    /// same as `Volatile::on_message`
+    fn on_message(&mut self, _state: &GameState, _message: &Message) {}
+
+    /// same as `Volatile::subscribed`
+    fn subscribed(&self, _channel: &str) -> bool {
+        false
+    }
+
+    /// same as `Volatile::sort_key`
+    fn sort_key(&self) -> f32 {
+        0f32
+    }
+
+    /// same as `Volatile::on_spawn`. also called for entities created by load,
+    /// before on_loaded, but not for those created by start_replay
+    fn on_spawn(&mut self, _state: &GameState) {}
+
+    /// same as `Volatile::on_despawn`. also called for entities replaced by
+    /// load, whose returned spawns are dropped
+    fn on_despawn(&mut self, _state: &GameState) -> Vec<(&'static str, Vec<VolatileSpawn>)> {
+        Vec::new()
+    }
+
+    /// same as `Volatile::on_loaded`. this happens after load_entity_references
+    fn on_loaded(&mut self, _state: &GameState) {}
 
     /// references to Persistent objects which need to be saved
     fn save_entity_references(&self) -> Vec<MaybePersistentRef> {
@@ -129,63 +263,228 @@ impl VolatileEntity {
         r
     }
 
-    fn render(&self, canvas: &mut WindowCanvas) {
+    fn render(&self, renderer: &mut dyn Renderer, info: &RenderInfo) {
+        let e = self.0.take().unwrap();
+        e.render(renderer, info);
+        self.0.set(Some(e));
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let e = self.0.take().unwrap();
+        let r = e.bounds();
+        self.0.set(Some(e));
+        r
+    }
+
+    fn collider(&self) -> Option<Collider> {
+        let e = self.0.take().unwrap();
+        let r = e.collider();
+        self.0.set(Some(e));
+        r
+    }
+
+    fn integrate(&self, dt: f32, gravity: (f32, f32)) {
+        let mut e = self.0.take().unwrap();
+        if let Some(body) = e.rigid_body() {
+            body.integrate(dt, gravity);
+        }
+        self.0.set(Some(e));
+    }
+
+    fn on_message(&self, state: &GameState, message: &Message) {
+        let mut e = self.0.take().unwrap();
+        e.on_message(state, message);
+        self.0.set(Some(e));
+    }
+
+    fn subscribed(&self, channel: &str) -> bool {
+        let e = self.0.take().unwrap();
+        let r = e.subscribed(channel);
+        self.0.set(Some(e));
+        r
+    }
+
+    fn concrete_type(&self) -> TypeId {
+        let e = self.0.take().unwrap();
+        let r = Any::type_id(e.as_ref().as_any());
+        self.0.set(Some(e));
+        r
+    }
+
+    fn sort_key(&self) -> f32 {
         let e = self.0.take().unwrap();
-        e.render(canvas);
+        let r = e.sort_key();
+        self.0.set(Some(e));
+        r
+    }
+
+    fn on_spawn(&self, state: &GameState) {
+        let mut e = self.0.take().unwrap();
+        e.on_spawn(state);
+        self.0.set(Some(e));
+    }
+
+    fn on_despawn(&self, state: &GameState) -> Vec<(&'static str, Vec<VolatileSpawn>)> {
+        let mut e = self.0.take().unwrap();
+        let r = e.on_despawn(state);
+        self.0.set(Some(e));
+        r
+    }
+
+    fn on_loaded(&self, state: &GameState) {
+        let mut e = self.0.take().unwrap();
+        e.on_loaded(state);
         self.0.set(Some(e));
     }
 }
 
+/// identifies a persistent entity. it's allocated when the entity is spawned
+/// and stays the same through save and load. ids are never reused within a
+/// game state (or its save files)
+#[derive(
+    Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, serde::Serialize, serde::Deserialize,
+)]
+pub struct EntityId(pub u64);
+
+/// what the game state and persistent refs point to
+pub struct PersistentCell {
+    pub id: EntityId,
+    pub e: Cell<Option<Box<dyn Persistent>>>,
+}
+
 // shared pointer to persistent
-pub struct PersistentEntity(pub Rc<Cell<Option<Box<dyn Persistent>>>>);
+pub struct PersistentEntity(pub Rc<PersistentCell>);
 
 // functions forward to Persistent
 impl PersistentEntity {
+    fn new(id: EntityId, e: Box<dyn Persistent>) -> Self {
+        PersistentEntity(Rc::new(PersistentCell {
+            id,
+            e: Cell::new(Some(e)),
+        }))
+    }
+
     fn clone(&self) -> Self {
         PersistentEntity(self.0.clone())
     }
 
+    pub fn id(&self) -> EntityId {
+        self.0.id
+    }
+
+    /// a weak reference to this entity
+    pub fn downgrade(&self) -> PersistentRef {
+        PersistentRef(Rc::downgrade(&self.0))
+    }
+
     fn generate_rate(&self, state: &GameState) {
-        let mut e = self.0.take().unwrap();
+        let mut e = self.0.e.take().unwrap();
         e.generate_rate(state);
-        self.0.set(Some(e));
+        self.0.e.set(Some(e));
     }
 
     fn apply_rate(&self) {
-        let mut e = self.0.take().unwrap();
+        let mut e = self.0.e.take().unwrap();
         e.apply_rate();
-        self.0.set(Some(e));
+        self.0.e.set(Some(e));
     }
 
     fn apply_spawns(&self) -> PersistentSpawnChanges {
-        let e = self.0.take().unwrap();
+        let e = self.0.e.take().unwrap();
         let r = e.apply_spawns();
-        self.0.set(Some(e));
+        self.0.e.set(Some(e));
         r
     }
 
-    fn render(&self, canvas: &mut WindowCanvas) {
-        let e = self.0.take().unwrap();
-        e.render(canvas);
-        self.0.set(Some(e));
+    fn render(&self, renderer: &mut dyn Renderer, info: &RenderInfo) {
+        let e = self.0.e.take().unwrap();
+        e.render(renderer, info);
+        self.0.e.set(Some(e));
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let e = self.0.e.take().unwrap();
+        let r = e.bounds();
+        self.0.e.set(Some(e));
+        r
+    }
+
+    fn collider(&self) -> Option<Collider> {
+        let e = self.0.e.take().unwrap();
+        let r = e.collider();
+        self.0.e.set(Some(e));
+        r
+    }
+
+    fn integrate(&self, dt: f32, gravity: (f32, f32)) {
+        let mut e = self.0.e.take().unwrap();
+        if let Some(body) = e.rigid_body() {
+            body.integrate(dt, gravity);
+        }
+        self.0.e.set(Some(e));
+    }
+
+    fn on_message(&self, state: &GameState, message: &Message) {
+        let mut e = self.0.e.take().unwrap();
+        e.on_message(state, message);
+        self.0.e.set(Some(e));
+    }
+
+    fn subscribed(&self, channel: &str) -> bool {
+        let e = self.0.e.take().unwrap();
+        let r = e.subscribed(channel);
+        self.0.e.set(Some(e));
+        r
+    }
+
+    fn concrete_type(&self) -> TypeId {
+        let e = self.0.e.take().unwrap();
+        let r = Any::type_id(e.as_ref().as_any());
+        self.0.e.set(Some(e));
+        r
+    }
+
+    fn sort_key(&self) -> f32 {
+        let e = self.0.e.take().unwrap();
+        let r = e.sort_key();
+        self.0.e.set(Some(e));
+        r
+    }
+
+    fn on_spawn(&self, state: &GameState) {
+        let mut e = self.0.e.take().unwrap();
+        e.on_spawn(state);
+        self.0.e.set(Some(e));
+    }
+
+    fn on_despawn(&self, state: &GameState) -> Vec<(&'static str, Vec<VolatileSpawn>)> {
+        let mut e = self.0.e.take().unwrap();
+        let r = e.on_despawn(state);
+        self.0.e.set(Some(e));
+        r
+    }
+
+    fn on_loaded(&self, state: &GameState) {
+        let mut e = self.0.e.take().unwrap();
+        e.on_loaded(state);
+        self.0.e.set(Some(e));
     }
 
     fn save_entity_references(&self) -> Vec<MaybePersistentRef> {
-        let e = self.0.take().unwrap();
+        let e = self.0.e.take().unwrap();
         let r = e.save_entity_references();
-        self.0.set(Some(e));
+        self.0.e.set(Some(e));
         r
     }
 
     fn load_entity_references(&self, v: Vec<MaybePersistentRef>) {
-        let mut e = self.0.take().unwrap();
+        let mut e = self.0.e.take().unwrap();
         e.load_entity_references(v);
-        self.0.set(Some(e));
+        self.0.e.set(Some(e));
     }
 }
 
-// hash and equality operators based on pointer address for use in unordered set
-// and unordered map when loading and saving
+// hash and equality operators based on pointer address
 impl PartialEq for PersistentEntity {
     fn eq(&self, other: &Self) -> bool {
         Rc::ptr_eq(&self.0, &other.0)
@@ -201,7 +500,7 @@ impl std::hash::Hash for PersistentEntity {
     }
 }
 
-pub struct PersistentRef(pub Weak<Cell<Option<Box<dyn Persistent>>>>);
+pub struct PersistentRef(pub Weak<PersistentCell>);
 
 impl Clone for PersistentRef {
     fn clone(&self) -> Self {
@@ -250,14 +549,15 @@ pub enum PersistentRefPromotionResult {
 }
 
 impl PersistentRef {
-    // if Some is returned, it must be followed by a corresponding call to set
+    // if Some is returned, it must be followed by a corresponding call to set.
+    // guard, with and with_mut do this automatically
     pub fn get(&self) -> PersistentRefPromotionResult {
         let rc = match self.0.upgrade() {
             Some(rc) => rc,
             None => return PersistentRefPromotionResult::Despawned,
         };
 
-        match rc.take() {
+        match rc.e.take() {
             Some(e) => PersistentRefPromotionResult::Some(PersistentEntity(rc), e),
             None => PersistentRefPromotionResult::Taken,
         }
@@ -265,166 +565,543 @@ impl PersistentRef {
 
     // return the PersistentEntity back to its position
     pub fn set(s: (PersistentEntity, Box<dyn Persistent>)) {
-        s.0 .0.set(Some(s.1));
+        s.0 .0.e.set(Some(s.1));
     }
-}
 
-pub type PersistentSpawn = Box<dyn Persistent>;
-pub type VolatileRef = Weak<Cell<Option<Box<dyn Volatile>>>>;
-pub type VolatileSpawn = Box<dyn Volatile>;
+    /// None if the entity has despawned
+    pub fn id(&self) -> Option<EntityId> {
+        self.0.upgrade().map(|rc| rc.id)
+    }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-/// corresponds to variants of `MaybePersistentRef`
-enum Tag {
-    None,
-    Despawned,
-    Some(u64),
+    /// same as get, except the entity is put back automatically when the
+    /// guard is dropped
+    pub fn guard(&self) -> PersistentGuardResult {
+        match self.get() {
+            PersistentRefPromotionResult::Despawned => PersistentGuardResult::Despawned,
+            PersistentRefPromotionResult::Taken => PersistentGuardResult::Taken,
+            PersistentRefPromotionResult::Some(rc, e) => PersistentGuardResult::Some(PersistentGuard {
+                rc: rc.0,
+                e: Some(e),
+            }),
+        }
+    }
+
+    /// look at the entity as its concrete type. the entity is always put
+    /// back, even if f panics
+    pub fn with<T: Persistent, R>(&self, f: impl FnOnce(&T) -> R) -> PersistentRefAccess<R> {
+        match self.guard() {
+            PersistentGuardResult::Some(guard) => match guard.downcast_ref::<T>() {
+                Some(e) => PersistentRefAccess::Some(f(e)),
+                None => PersistentRefAccess::WrongType,
+            },
+            PersistentGuardResult::Despawned => PersistentRefAccess::Despawned,
+            PersistentGuardResult::Taken => PersistentRefAccess::Taken,
+        }
+    }
+
+    /// same as with, but allows modification of the entity
+    pub fn with_mut<T: Persistent, R>(&self, f: impl FnOnce(&mut T) -> R) -> PersistentRefAccess<R> {
+        match self.guard() {
+            PersistentGuardResult::Some(mut guard) => match guard.downcast_mut::<T>() {
+                Some(e) => PersistentRefAccess::Some(f(e)),
+                None => PersistentRefAccess::WrongType,
+            },
+            PersistentGuardResult::Despawned => PersistentRefAccess::Despawned,
+            PersistentGuardResult::Taken => PersistentRefAccess::Taken,
+        }
+    }
 }
 
-// used internally for PersistentState serialize and deserialize
-struct TaggedPersistent {
-    // the subject persistent entity. it takes a clone of the Rc the game state has
-    e: PersistentEntity,
-    // the id for this entity
-    tag: u64,
-    // the tags that this entity points to
-    refs: Vec<Tag>,
+/// result of PersistentRef::guard
+pub enum PersistentGuardResult {
+    /// same as `PersistentRefPromotionResult::Despawned`
+    Despawned,
+    /// same as `PersistentRefPromotionResult::Taken`
+    Taken,
+    Some(PersistentGuard),
 }
 
-impl serde::Serialize for TaggedPersistent {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut state = serializer.serialize_struct("TaggedPersistent", 3)?;
-        let e = self.e.0.take().unwrap();
-        state.serialize_field("e", &e)?;
-        self.e.0.set(Some(e));
-        state.serialize_field("tag", &self.tag)?;
-        state.serialize_field("refs", &self.refs)?;
-        state.end()
+impl PersistentGuardResult {
+    /// discards the reason for failure
+    pub fn ok(self) -> Option<PersistentGuard> {
+        match self {
+            PersistentGuardResult::Some(guard) => Some(guard),
+            _ => None,
+        }
     }
 }
 
-impl<'de> serde::Deserialize<'de> for TaggedPersistent {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        #[derive(serde::Deserialize)]
-        struct TaggedPersistentHelper {
-            e: Box<dyn Persistent>,
-            tag: u64,
-            refs: Vec<Tag>,
-        }
+/// result of accessing an entity through a PersistentRef with with or
+/// with_mut
+pub enum PersistentRefAccess<R> {
+    /// same as `PersistentRefPromotionResult::Despawned`
+    Despawned,
+    /// same as `PersistentRefPromotionResult::Taken`
+    Taken,
+    /// the entity isn't the requested concrete type
+    WrongType,
+    Some(R),
+}
 
-        let helper: TaggedPersistentHelper = serde::de::Deserialize::deserialize(deserializer)?;
-        Ok(TaggedPersistent {
-            e: PersistentEntity(Rc::new(Cell::new(Some(helper.e)))),
-            tag: helper.tag,
-            refs: helper.refs,
-        })
+impl<R> PersistentRefAccess<R> {
+    /// discards the reason for failure
+    pub fn ok(self) -> Option<R> {
+        match self {
+            PersistentRefAccess::Some(r) => Some(r),
+            _ => None,
+        }
     }
 }
 
-/// section of GameState that has saveable things
-#[derive(serde::Serialize)]
-struct PersistentState {
-    /// associates layer name with persistent entities in that layer
-    #[serde(
-        serialize_with = "PersistentState::serialize_layers",
-        deserialize_with = "PersistentState::deserialize_layers"
-    )]
-    pub persistent_layers: BTreeMap<&'static str, Vec<PersistentEntity>>,
+/// exclusive access to a persistent entity. it's returned to its place in the
+/// game state when this is dropped (including while unwinding from a panic)
+pub struct PersistentGuard {
+    rc: Rc<PersistentCell>,
+    // only None during drop
+    e: Option<Box<dyn Persistent>>,
 }
 
-impl PersistentState {
-    fn new(layer_names: &'static [&'static str]) -> Self {
-        let persistent_layers: BTreeMap<&'static str, Vec<PersistentEntity>> =
-            layer_names.iter().map(|key| (*key, Vec::new())).collect();
-        Self { persistent_layers }
+impl PersistentGuard {
+    pub fn id(&self) -> EntityId {
+        self.rc.id
     }
+}
 
-    // saving has linear time complexity with the number of elements
-    fn serialize_layers<S>(
-        layers: &BTreeMap<&'static str, Vec<PersistentEntity>>,
-        serializer: S,
-    ) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut state = serializer.serialize_map(Some(layers.len()))?;
-        let mut next_tag: u64 = 0;
-
-        let mut tagged_entities: BTreeMap<&'static str, Vec<TaggedPersistent>> = BTreeMap::new();
+impl std::ops::Deref for PersistentGuard {
+    type Target = dyn Persistent;
 
-        // associates entities with their tags. for time complexity later
-        let mut lookup_tag: HashMap<PersistentEntity, u64> = HashMap::new();
+    fn deref(&self) -> &Self::Target {
+        self.e.as_deref().unwrap()
+    }
+}
 
-        // for all entities in the layers get a rc clone (serde requires data to
-        // be owned), and set a tag uniquely identifying PersistentEntities
-        for (key, entities) in layers {
-            let mut tagged_entities_in_layer: Vec<TaggedPersistent> = Vec::new();
-            for entity in entities.iter() {
-                // create the lookup association, but don't bother if it's
-                // guarenteed that this association will not be used
-                if Rc::weak_count(&entity.0) != 0 {
-                    // this if statement errs on the side of caution:
-                    // it's possible that the weak count will not be zero from a
-                    // Volatile looking at this, in which case it will create a
-                    // association that's not used (only persistent ->
-                    // persistent weak ref is saved and loaded), but that's
-                    // fine. this check doesn't really matter anyway
-                    lookup_tag.insert(entity.clone(), next_tag);
-                }
+impl std::ops::DerefMut for PersistentGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.e.as_deref_mut().unwrap()
+    }
+}
 
-                tagged_entities_in_layer.push(TaggedPersistent {
-                    e: entity.clone(),
-                    tag: next_tag,
-                    refs: Vec::new(), // populated in next step
-                });
-                next_tag += 1;
-            }
+impl Drop for PersistentGuard {
+    fn drop(&mut self) {
+        self.rc.e.set(self.e.take());
+    }
+}
 
-            tagged_entities.insert(key, tagged_entities_in_layer);
-        }
+/// a persistent entity borrowed from the game state as its concrete type.
+/// it's returned to its place when this is dropped. see
+/// `GameState::persistents_of`
+pub struct PersistentView<'a, T: Persistent> {
+    entity: &'a PersistentEntity,
+    // only None during drop
+    e: Option<Box<T>>,
+}
 
-        // next pass sets the refs to their tags
-        for (_, tagged_entities_in_layer) in tagged_entities.iter_mut() {
-            for tagged_entity in tagged_entities_in_layer.iter_mut() {
-                for maybe_weak in tagged_entity.e.save_entity_references() {
-                    match maybe_weak {
-                        MaybePersistentRef::None => tagged_entity.refs.push(Tag::None),
-                        MaybePersistentRef::Despawned => tagged_entity.refs.push(Tag::Despawned),
-                        MaybePersistentRef::Some(weak) => {
-                            let strong = weak.0.upgrade();
-                            if strong.is_none() {
-                                tagged_entity.refs.push(Tag::Despawned);
-                            } else {
-                                let p = PersistentEntity(strong.unwrap());
-                                let tag = lookup_tag.get(&p).unwrap();
-                                tagged_entity.refs.push(Tag::Some(*tag));
-                            }
-                        }
-                    }
-                }
+impl<'a, T: Persistent> PersistentView<'a, T> {
+    /// None if the entity is taken or isn't of type T
+    fn new(entity: &'a PersistentEntity) -> Option<Self> {
+        let e = entity.0.e.take()?;
+        match e.downcast::<T>() {
+            Ok(e) => Some(Self { entity, e: Some(e) }),
+            Err(e) => {
+                entity.0.e.set(Some(e));
+                None
             }
         }
+    }
 
-        // do serialization
-        for (k, v) in tagged_entities.into_iter() {
-            state.serialize_entry(&k, &v)?;
-        }
+    pub fn id(&self) -> EntityId {
+        self.entity.id()
+    }
 
-        state.end()
+    /// a weak reference to this entity, e.g. to be stored in a
+    /// MaybePersistentRef
+    pub fn downgrade(&self) -> PersistentRef {
+        self.entity.downgrade()
     }
 }
 
-// this is a PersistentState, but a temporary during deserialization. it uses String instead of str
-#[derive(serde::Deserialize)]
-struct PersistentStateTemp {
-    #[serde(deserialize_with = "PersistentStateTemp::deserialize_layers")]
-    pub persistent_layers: BTreeMap<String, Vec<PersistentEntity>>,
+impl<'a, T: Persistent> std::ops::Deref for PersistentView<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.e.as_deref().unwrap()
+    }
+}
+
+impl<'a, T: Persistent> Drop for PersistentView<'a, T> {
+    fn drop(&mut self) {
+        let e: Box<dyn Persistent> = self.e.take().unwrap();
+        self.entity.0.e.set(Some(e));
+    }
+}
+
+/// same as PersistentView, but for volatile entities. see
+/// `GameState::volatiles_of`
+pub struct VolatileView<'a, T: Volatile> {
+    entity: &'a VolatileEntity,
+    // only None during drop
+    e: Option<Box<T>>,
+}
+
+impl<'a, T: Volatile> VolatileView<'a, T> {
+    /// None if the entity is taken or isn't of type T
+    fn new(entity: &'a VolatileEntity) -> Option<Self> {
+        let e = entity.0.take()?;
+        match e.downcast::<T>() {
+            Ok(e) => Some(Self { entity, e: Some(e) }),
+            Err(e) => {
+                entity.0.set(Some(e));
+                None
+            }
+        }
+    }
+}
+
+impl<'a, T: Volatile> std::ops::Deref for VolatileView<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.e.as_deref().unwrap()
+    }
+}
+
+impl<'a, T: Volatile> Drop for VolatileView<'a, T> {
+    fn drop(&mut self) {
+        let e: Box<dyn Volatile> = self.e.take().unwrap();
+        self.entity.0.set(Some(e));
+    }
+}
+
+/// an entity found in the spatial index
+#[derive(Clone)]
+pub enum SpatialEntity {
+    Persistent(PersistentRef),
+    Volatile(VolatileRef),
+}
+
+/// result of a query on the spatial index
+#[derive(Clone)]
+pub struct SpatialEntry {
+    /// the layer that the entity is in
+    pub layer: &'static str,
+    /// the entity's bounds as of the start of the current step
+    pub bounds: Aabb,
+    pub entity: SpatialEntity,
+}
+
+impl SpatialEntity {
+    // nothing happens if the entity has despawned
+    fn on_collision(&self, state: &GameState, collision: &Collision) {
+        match self {
+            SpatialEntity::Persistent(r) => {
+                if let Some(rc) = r.0.upgrade() {
+                    let mut e = rc.e.take().expect("entity in use during collision");
+                    e.on_collision(state, collision);
+                    rc.e.set(Some(e));
+                }
+            }
+            SpatialEntity::Volatile(r) => {
+                if let Some(rc) = r.upgrade() {
+                    let mut e = rc.take().expect("entity in use during collision");
+                    e.on_collision(state, collision);
+                    rc.set(Some(e));
+                }
+            }
+        }
+    }
+
+    // nothing happens if the entity has despawned or has no body
+    fn with_rigid_body<F: FnOnce(&mut RigidBody)>(&self, f: F) {
+        match self {
+            SpatialEntity::Persistent(r) => {
+                if let Some(rc) = r.0.upgrade() {
+                    let mut e = rc.e.take().expect("entity in use during physics");
+                    if let Some(body) = e.rigid_body() {
+                        f(body);
+                    }
+                    rc.e.set(Some(e));
+                }
+            }
+            SpatialEntity::Volatile(r) => {
+                if let Some(rc) = r.upgrade() {
+                    let mut e = rc.take().expect("entity in use during physics");
+                    if let Some(body) = e.rigid_body() {
+                        f(body);
+                    }
+                    rc.set(Some(e));
+                }
+            }
+        }
+    }
+}
+
+/// given to `on_collision`, describing the other entity
+pub struct Collision {
+    pub phase: CollisionPhase,
+    pub other: SpatialEntity,
+    /// the render layer that the other entity is in
+    pub other_layer: &'static str,
+    /// for Exit, the other collider as of the last tick it overlapped
+    pub other_collider: Collider,
+}
+
+// identifies an entity across ticks, for telling enter from stay.
+// volatiles are identified by address, which can't be reused while the
+// contact holds a weak reference
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum ContactKey {
+    Persistent(EntityId),
+    Volatile(usize),
+}
+
+impl ContactKey {
+    // the same whichever order the sides were found in
+    fn pair(a: ContactKey, b: ContactKey) -> (ContactKey, ContactKey) {
+        (a.min(b), a.max(b))
+    }
+}
+
+#[derive(Clone)]
+struct ContactSide {
+    key: ContactKey,
+    layer: &'static str,
+    entity: SpatialEntity,
+    collider: Collider,
+}
+
+// a pair of overlapping colliders
+struct Contact {
+    a: ContactSide,
+    b: ContactSide,
+}
+
+impl Contact {
+    fn keys(&self) -> (ContactKey, ContactKey) {
+        ContactKey::pair(self.a.key, self.b.key)
+    }
+}
+
+pub type PersistentSpawn = Box<dyn Persistent>;
+pub type VolatileRef = Weak<Cell<Option<Box<dyn Volatile>>>>;
+pub type VolatileSpawn = Box<dyn Volatile>;
+
+/// random number generator owned by the game state. see `GameState::rng`
+pub type GameRng = rand_chacha::ChaCha8Rng;
+
+/// position within a GameRng's sequence, as it is stored in the save file
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RngState {
+    seed: [u8; 32],
+    stream: u64,
+    // u128 split into high and low halves. not all serde formats support u128
+    word_pos: [u64; 2],
+}
+
+impl RngState {
+    fn new(rng: &GameRng) -> Self {
+        let word_pos = rng.get_word_pos();
+        Self {
+            seed: rng.get_seed(),
+            stream: rng.get_stream(),
+            word_pos: [(word_pos >> 64) as u64, word_pos as u64],
+        }
+    }
+
+    fn restore(&self) -> GameRng {
+        let mut rng = GameRng::from_seed(self.seed);
+        rng.set_stream(self.stream);
+        rng.set_word_pos((u128::from(self.word_pos[0]) << 64) | u128::from(self.word_pos[1]));
+        rng
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+/// corresponds to variants of `MaybePersistentRef`
+enum Tag {
+    None,
+    Despawned,
+    Some(u64),
+}
+
+// used internally for PersistentState serialize and deserialize
+struct TaggedPersistent {
+    // the subject persistent entity. it takes a clone of the Rc the game state has
+    e: PersistentEntity,
+    // the id for this entity
+    tag: u64,
+    // the tags that this entity points to
+    refs: Vec<Tag>,
+}
+
+impl serde::Serialize for TaggedPersistent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("TaggedPersistent", 3)?;
+        let e = self.e.0.e.take().unwrap();
+        state.serialize_field("e", &e)?;
+        self.e.0.e.set(Some(e));
+        state.serialize_field("tag", &self.tag)?;
+        state.serialize_field("refs", &self.refs)?;
+        state.end()
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TaggedPersistent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(serde::Deserialize)]
+        struct TaggedPersistentHelper {
+            e: Box<dyn Persistent>,
+            tag: u64,
+            refs: Vec<Tag>,
+        }
+
+        let helper: TaggedPersistentHelper = serde::de::Deserialize::deserialize(deserializer)?;
+        Ok(TaggedPersistent {
+            e: PersistentEntity::new(EntityId(helper.tag), helper.e),
+            tag: helper.tag,
+            refs: helper.refs,
+        })
+    }
+}
+
+/// section of GameState that has saveable things
+#[derive(serde::Serialize)]
+struct PersistentState {
+    /// associates layer name with persistent entities in that layer
+    #[serde(
+        serialize_with = "PersistentState::serialize_layers",
+        deserialize_with = "PersistentState::deserialize_layers"
+    )]
+    pub persistent_layers: BTreeMap<&'static str, Vec<PersistentEntity>>,
+
+    /// number of times the simulation has been stepped
+    tick: u64,
+
+    /// sum of the tick duration over each step
+    simulated_time: std::time::Duration,
+
+    /// saved so that loading continues the same random sequence
+    #[serde(serialize_with = "PersistentState::serialize_rng")]
+    rng: RefCell<GameRng>,
+
+    /// the id given to the next spawned persistent entity
+    next_id: u64,
+
+    /// associates ids with every entity in persistent_layers
+    #[serde(skip)]
+    id_lookup: HashMap<EntityId, (&'static str, PersistentRef)>,
+}
+
+impl PersistentState {
+    fn new(layer_names: &[&'static str]) -> Self {
+        let persistent_layers: BTreeMap<&'static str, Vec<PersistentEntity>> =
+            layer_names.iter().map(|key| (*key, Vec::new())).collect();
+        Self {
+            persistent_layers,
+            tick: 0,
+            simulated_time: std::time::Duration::ZERO,
+            rng: RefCell::new(GameRng::from_entropy()),
+            next_id: 0,
+            id_lookup: HashMap::new(),
+        }
+    }
+
+    /// wrap a new entity for a layer, giving it the next id
+    fn new_entity(&mut self, e: PersistentSpawn, layer: &'static str) -> PersistentEntity {
+        let entity = PersistentEntity::new(EntityId(self.next_id), e);
+        self.next_id += 1;
+        self.id_lookup
+            .insert(entity.id(), (layer, entity.downgrade()));
+        entity
+    }
+
+    fn rebuild_id_lookup(&mut self) {
+        self.id_lookup = self
+            .persistent_layers
+            .iter()
+            .flat_map(|(layer, entities)| entities.iter().map(move |entity| (*layer, entity)))
+            .map(|(layer, entity)| (entity.id(), (layer, entity.downgrade())))
+            .collect();
+    }
+
+    fn serialize_rng<S>(rng: &RefCell<GameRng>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serde::Serialize::serialize(&RngState::new(&rng.borrow()), serializer)
+    }
+
+    // saving has linear time complexity with the number of elements
+    fn serialize_layers<S>(
+        layers: &BTreeMap<&'static str, Vec<PersistentEntity>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_map(Some(layers.len()))?;
+
+        let mut tagged_entities: BTreeMap<&'static str, Vec<TaggedPersistent>> = BTreeMap::new();
+
+        // for all entities in the layers get a rc clone (serde requires data to
+        // be owned). the tag uniquely identifying each entity is its id
+        for (key, entities) in layers {
+            let mut tagged_entities_in_layer: Vec<TaggedPersistent> = Vec::new();
+            for entity in entities.iter() {
+                let refs = entity
+                    .save_entity_references()
+                    .into_iter()
+                    .map(|maybe_weak| match maybe_weak {
+                        MaybePersistentRef::None => Tag::None,
+                        MaybePersistentRef::Despawned => Tag::Despawned,
+                        MaybePersistentRef::Some(weak) => match weak.0.upgrade() {
+                            Some(strong) => Tag::Some(strong.id.0),
+                            None => Tag::Despawned,
+                        },
+                    })
+                    .collect();
+
+                tagged_entities_in_layer.push(TaggedPersistent {
+                    e: entity.clone(),
+                    tag: entity.id().0,
+                    refs,
+                });
+            }
+
+            tagged_entities.insert(key, tagged_entities_in_layer);
+        }
+
+        // do serialization
+        for (k, v) in tagged_entities.into_iter() {
+            state.serialize_entry(&k, &v)?;
+        }
+
+        state.end()
+    }
+}
+
+// this is a PersistentState, but a temporary during deserialization. it uses String instead of str
+#[derive(serde::Deserialize)]
+struct PersistentStateTemp {
+    #[serde(deserialize_with = "PersistentStateTemp::deserialize_layers")]
+    pub persistent_layers: BTreeMap<String, Vec<PersistentEntity>>,
+
+    // save files from before these were added start from zero
+    #[serde(default)]
+    tick: u64,
+    #[serde(default)]
+    simulated_time: std::time::Duration,
+    // save files without it keep the current rng
+    #[serde(default)]
+    rng: Option<RngState>,
+    // save files without it continue from the largest id in the file
+    #[serde(default)]
+    next_id: Option<u64>,
 }
 
 macro_rules! debug_assert_layers_rc_sanity {
@@ -448,21 +1125,29 @@ macro_rules! debug_assert_layers_rc_sanity {
 }
 
 impl PersistentStateTemp {
-    fn replace(&mut self, state: &mut PersistentState) -> Result<(), String> {
-        if !self
-            .persistent_layers
-            .keys()
-            .eq(state.persistent_layers.keys())
-        {
-            return Err("loaded save file doesn't contain correct render layers".to_owned());
-        }
-
+    // returns the entities which were replaced, so that they can be given
+    // on_despawn
+    fn replace(&mut self, state: &mut PersistentState) -> Result<Vec<PersistentEntity>, String> {
         debug_assert_layers_rc_sanity!(&state.persistent_layers);
+        // the save file's layers can differ from those registered. entities in
+        // unregistered layers are dropped (references to them load as
+        // despawned), and registered layers missing from the save are empty
+        let mut replaced = Vec::new();
         for (k, to) in state.persistent_layers.iter_mut() {
-            let from = self.persistent_layers.get_mut(k.to_owned()).unwrap();
-            std::mem::swap(to, from);
+            let from = self.persistent_layers.get_mut(*k).map(std::mem::take);
+            replaced.append(&mut std::mem::replace(to, from.unwrap_or_default()));
         }
-        Ok(())
+        state.tick = self.tick;
+        state.simulated_time = self.simulated_time;
+        if let Some(rng) = &self.rng {
+            state.rng = RefCell::new(rng.restore());
+        }
+        state.rebuild_id_lookup();
+        state.next_id = match self.next_id {
+            Some(next_id) => next_id,
+            None => state.id_lookup.keys().map(|id| id.0 + 1).max().unwrap_or(0),
+        };
+        Ok(replaced)
     }
 
     // loading has same time complexity as saving
@@ -528,10 +1213,11 @@ impl PersistentStateTemp {
                     .refs
                     .iter()
                     .map(|r| match r {
-                        Tag::Some(u) => {
-                            let r = Rc::downgrade(&lookup_entity.get(u).unwrap().0);
-                            MaybePersistentRef::Some(PersistentRef(r))
-                        }
+                        Tag::Some(u) => match lookup_entity.get(u) {
+                            Some(e) => MaybePersistentRef::Some(e.downgrade()),
+                            // points to an entity which isn't in the save file
+                            None => MaybePersistentRef::Despawned,
+                        },
                         Tag::Despawned => MaybePersistentRef::Despawned,
                         Tag::None => MaybePersistentRef::None,
                     })
@@ -546,18 +1232,162 @@ impl PersistentStateTemp {
     }
 }
 
+/// per layer options. see `GameState::add_layer`
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LayerSettings {
+    /// entities in the layer are rendered
+    pub visible: bool,
+    /// entities in the layer are updated by step. a layer which isn't is
+    /// "paused", e.g. the game world under a pause menu
+    pub update_enabled: bool,
+    /// how much the layer moves with the camera. 1 moves with the world, 0 is
+    /// fixed to the screen, and values between are for backgrounds
+    pub parallax: f32,
+    /// entities in the layer are cleared by change_scene
+    pub clear_on_scene_change: bool,
+    /// the layer is drawn through each camera. otherwise it's drawn once over
+    /// the whole window with world coordinates as pixels from the top left,
+    /// e.g. for the HUD
+    pub world_space: bool,
+    /// entities in the layer are drawn in order of their sort_key. otherwise
+    /// volatiles are drawn before persistents, each in spawn order
+    pub sorted: bool,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            update_enabled: true,
+            parallax: 1f32,
+            clear_on_scene_change: true,
+            world_space: true,
+            sorted: true,
+        }
+    }
+}
+
+// either type of entity, for drawing a layer in sorted order
+#[derive(Clone, Copy)]
+enum LayerEntity<'a> {
+    Volatile(&'a VolatileEntity),
+    Persistent(&'a PersistentEntity),
+}
+
+impl LayerEntity<'_> {
+    fn sort_key(&self) -> f32 {
+        match self {
+            LayerEntity::Volatile(e) => e.sort_key(),
+            LayerEntity::Persistent(e) => e.sort_key(),
+        }
+    }
+
+    fn render(&self, renderer: &mut dyn Renderer, info: &RenderInfo) {
+        match self {
+            LayerEntity::Volatile(e) => e.render(renderer, info),
+            LayerEntity::Persistent(e) => e.render(renderer, info),
+        }
+    }
+
+    fn on_message(&self, state: &GameState, message: &Message) {
+        match self {
+            LayerEntity::Volatile(e) => e.on_message(state, message),
+            LayerEntity::Persistent(e) => e.on_message(state, message),
+        }
+    }
+
+    fn subscribed(&self, channel: &str) -> bool {
+        match self {
+            LayerEntity::Volatile(e) => e.subscribed(channel),
+            LayerEntity::Persistent(e) => e.subscribed(channel),
+        }
+    }
+
+    fn concrete_type(&self) -> TypeId {
+        match self {
+            LayerEntity::Volatile(e) => e.concrete_type(),
+            LayerEntity::Persistent(e) => e.concrete_type(),
+        }
+    }
+}
+
+// in the order it's drawn
+fn layer_render_order<'a>(
+    volatiles: &'a [VolatileEntity],
+    persistents: &'a [PersistentEntity],
+    sorted: bool,
+) -> Vec<LayerEntity<'a>> {
+    let entities = volatiles
+        .iter()
+        .map(LayerEntity::Volatile)
+        .chain(persistents.iter().map(LayerEntity::Persistent));
+    if !sorted {
+        return entities.collect();
+    }
+    let mut keyed: Vec<(f32, LayerEntity)> = entities.map(|e| (e.sort_key(), e)).collect();
+    // stable, so ties keep their order
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+    keyed.into_iter().map(|(_, e)| e).collect()
+}
+
 pub struct GameState {
     /// render order of layers
-    layer_names: &'static [&'static str],
+    layer_names: Vec<&'static str>,
+    layer_settings: HashMap<&'static str, LayerSettings>,
+    /// world space layers are drawn through each, in order
+    cameras: Vec<Camera>,
+
+    /// simulated time between each call to step
+    tick_duration: std::time::Duration,
+
+    /// run doesn't step the simulation while paused, apart from requested advances
+    paused: bool,
+    /// number of ticks that run will step while paused
+    pending_advance: u64,
+    /// multiplier applied to real time before it's simulated by run
+    time_scale: f32,
+    /// run sleeps so that a frame takes at least this long. None to render as
+    /// fast as possible (or as vsync allows)
+    min_frame_duration: Option<std::time::Duration>,
+
+    /// while recording, events given to the event handler in run are added
+    recording: Option<Replay>,
+    /// while replaying, the events which haven't yet been given to the event
+    /// handler in run
+    replaying: Option<VecDeque<(u64, RecordedEvent)>>,
+
+    /// if enabled, rebuilt from entity bounds at the start of each step
+    spatial_index: Option<SpatialHash<SpatialEntry>>,
+
+    /// cell size of the grid used to find colliders that might overlap
+    collision_cell_size: f32,
+    /// overlapping colliders as of the last step
+    contacts: Vec<Contact>,
+    /// acceleration applied to every rigid body, in world units per second
+    /// squared
+    gravity: (f32, f32),
+    /// posted during this step, delivered at the start of the next
+    messages: RefCell<Vec<Message>>,
 
     persistent_state: PersistentState,
+    /// versions written to save files, and migrations from older ones
+    save_schema: SaveSchema,
+    /// how save writes files
+    save_format: SaveFormat,
+    /// number of previous saves kept beside each save file
+    save_backups: usize,
+    /// if enabled, run autosaves through this
+    autosaver: Option<Autosaver>,
 
     /// associates layer name with volatile entities in that layer
     volatile_layers: BTreeMap<&'static str, Vec<VolatileEntity>>,
 
+    /// no window is shown and the canvas is a software renderer
+    headless: bool,
+
     // sdl fundamentals. drop order is in stated order
     event_pump: sdl2::EventPump,
-    pub canvas: WindowCanvas,
+    pub canvas: WindowRenderer,
     _sdl_video_subsystem: sdl2::VideoSubsystem,
     _sdl_context: sdl2::Sdl,
 }
@@ -570,101 +1400,357 @@ impl Drop for GameState {
 }
 
 impl GameState {
-    pub const GOAL_FPS: f32 = 120f32;
-
-    /// intended to be used with save() and load().
-    #[allow(dead_code)]
-    fn gen_save_file_path(game_name: &'static str) -> String {
-        let current_time = std::time::SystemTime::now();
-        let duration_since_epoch = current_time.duration_since(std::time::UNIX_EPOCH).unwrap();
-        let epoch_time_seconds = duration_since_epoch.as_secs();
-
-        let mut pathbuf = dirs::data_local_dir().unwrap();
-        pathbuf.push(game_name.to_lowercase().replace(" ", "_"));
-        pathbuf.push("saves");
-        pathbuf.push("");
-        let path = pathbuf.to_str().unwrap();
-
-        format!(
-            "{}{}_{}",
-            path,
-            epoch_time_seconds.to_string(),
-            std::process::id().to_string()
-        )
-    }
+    /// ticks per second unless changed with set_tick_rate
+    pub const DEFAULT_TICK_RATE: f32 = 120f32;
+
+    /// the most real time that run will simulate for a single rendered frame.
+    /// after a long stall (e.g. window being dragged) the simulation skips
+    /// ahead instead of trying to catch up all at once
+    const MAX_FRAME_DURATION: std::time::Duration = std::time::Duration::from_millis(250);
+
+    /// frames per second that run renders at most unless changed with
+    /// set_max_frame_rate. above common refresh rates, so that it only has an
+    /// effect without vsync (e.g. headless)
+    pub const DEFAULT_MAX_FRAME_RATE: f32 = 240f32;
 
     /// create a game state, with associated window and sdl context. \
     /// `win_title` and `win_size` are used to set the properties of the window\
     /// `layer_names` is the set of layer names to register; used to indicate
-    /// RENDERING ORDER of sprites
+    /// RENDERING ORDER of sprites. they have default settings. more can be
+    /// added later with add_layer
     pub fn new(
         win_title: &'static str,
         win_size: (u32, u32),
         layer_names: &'static [&'static str],
     ) -> Result<Self, String> {
-        let sdl_context = sdl2::init()?;
-        let sdl_video_subsystem = sdl_context.video()?;
-        let window = sdl_video_subsystem
-            .window(win_title, win_size.0, win_size.1)
-            .resizable()
-            .position_centered()
-            .build()
-            .map_err(|e| e.to_string())?;
-        let mut canvas = window
-            .into_canvas()
-            .present_vsync()
-            .build()
-            .map_err(|e| e.to_string())?;
-        canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
-        let event_pump = sdl_context.event_pump()?;
-        let persistent_state = PersistentState::new(layer_names);
-        let volatile_layers: BTreeMap<&'static str, Vec<VolatileEntity>> =
-            layer_names.iter().map(|key| (*key, Vec::new())).collect();
+        Self::create(win_title, win_size, layer_names, false)
+    }
 
-        Ok(Self {
-            layer_names,
-            persistent_state,
-            volatile_layers,
+    /// create a game state which never shows a window. e.g. for tests or a
+    /// dedicated simulation server.\
+    /// sdl uses its dummy video driver and everything is rendered in software
+    /// to an offscreen target of size `win_size`. entities are updated and
+    /// rendered exactly the same as they would be with a window
+    pub fn new_headless(
+        win_size: (u32, u32),
+        layer_names: &'static [&'static str],
+    ) -> Result<Self, String> {
+        Self::create("headless", win_size, layer_names, true)
+    }
+
+    fn create(
+        win_title: &'static str,
+        win_size: (u32, u32),
+        layer_names: &'static [&'static str],
+        headless: bool,
+    ) -> Result<Self, String> {
+        if headless {
+            // must be set before the video subsystem is initialized
+            sdl2::hint::set("SDL_VIDEODRIVER", "dummy");
+        }
+        let sdl_context = sdl2::init()?;
+        let sdl_video_subsystem = sdl_context.video()?;
+        let mut window_builder = sdl_video_subsystem.window(win_title, win_size.0, win_size.1);
+        if headless {
+            window_builder.hidden();
+        } else {
+            window_builder.resizable().position_centered();
+        }
+        let window = window_builder.build().map_err(|e| e.to_string())?;
+        let mut canvas_builder = window.into_canvas();
+        if headless {
+            // there is no display to sync to
+            canvas_builder = canvas_builder.software();
+        } else {
+            canvas_builder = canvas_builder.present_vsync();
+        }
+        let mut canvas = canvas_builder.build().map_err(|e| e.to_string())?;
+        canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+        let event_pump = sdl_context.event_pump()?;
+        let persistent_state = PersistentState::new(layer_names);
+        let volatile_layers: BTreeMap<&'static str, Vec<VolatileEntity>> =
+            layer_names.iter().map(|key| (*key, Vec::new())).collect();
+        assert!(
+            volatile_layers.len() == layer_names.len(),
+            "duplicate layer names"
+        );
+
+        Ok(Self {
+            layer_names: layer_names.to_vec(),
+            layer_settings: layer_names
+                .iter()
+                .map(|key| (*key, LayerSettings::default()))
+                .collect(),
+            cameras: vec![Camera::default()],
+            tick_duration: std::time::Duration::from_secs_f32(1f32 / Self::DEFAULT_TICK_RATE),
+            paused: false,
+            pending_advance: 0,
+            time_scale: 1f32,
+            min_frame_duration: Some(std::time::Duration::from_secs_f32(
+                1f32 / Self::DEFAULT_MAX_FRAME_RATE,
+            )),
+            recording: None,
+            replaying: None,
+            spatial_index: None,
+            collision_cell_size: 64f32,
+            contacts: Vec::new(),
+            gravity: (0f32, 0f32),
+            messages: RefCell::new(Vec::new()),
+            persistent_state,
+            save_schema: SaveSchema::default(),
+            save_format: SaveFormat::default(),
+            save_backups: 1,
+            autosaver: None,
+            volatile_layers,
+            headless,
             event_pump,
-            canvas,
+            canvas: WindowRenderer::new(canvas)
+                .with_texture_cache(WindowRenderer::DEFAULT_TEXTURE_CACHE_CAPACITY),
             _sdl_video_subsystem: sdl_video_subsystem,
             _sdl_context: sdl_context,
         })
     }
 
-    /// overrides or creates new save file for the persistent entities
+    /// true if this was created with `new_headless`
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
+    pub fn save_schema(&self) -> &SaveSchema {
+        &self.save_schema
+    }
+
+    /// register the game version and entity migrations here, before loading
+    pub fn save_schema_mut(&mut self) -> &mut SaveSchema {
+        &mut self.save_schema
+    }
+
+    pub fn save_format(&self) -> SaveFormat {
+        self.save_format
+    }
+
+    /// compact binary by default. use `SaveFormat::JSON` to read saves while
+    /// debugging. load accepts any format
+    pub fn set_save_format(&mut self, format: SaveFormat) {
+        self.save_format = format;
+    }
+
+    pub fn save_backups(&self) -> usize {
+        self.save_backups
+    }
+
+    /// each save moves the previous one to a backup (path.bak1, then .bak2,
+    /// ...), keeping this many. load falls back to them if the save is corrupt.
+    /// 1 by default
+    pub fn set_save_backups(&mut self, backups: usize) {
+        self.save_backups = backups;
+    }
+
+    // the persistent state in a versioned envelope
+    fn save_envelope(&self) -> SaveEnvelope<'_, PersistentState> {
+        SaveEnvelope {
+            header: self.save_schema.header(),
+            state: &self.persistent_state,
+        }
+    }
+
+    fn save_value(&self) -> Result<serde_json::Value, String> {
+        serde_json::to_value(self.save_envelope()).map_err(|e| e.to_string())
+    }
+
+    // migrate a save value to the current schema and deserialize it
+    fn read_save_value(&self, save: serde_json::Value) -> Result<PersistentStateTemp, String> {
+        let (_header, state) = self.save_schema.migrate(save)?;
+        serde_json::from_value(state).map_err(|e| e.to_string())
+    }
+
+    fn write_save(&self, path: &Path, metadata: &SaveMetadata) -> Result<(), String> {
+        let bytes = save::encode(&self.save_envelope(), self.save_format, metadata)?;
+        save::write_atomic(path, &bytes, self.save_backups)
+    }
+
+    /// overrides or creates new save file for the persistent entities.\
+    /// the file is replaced atomically, so a failure partway through leaves
+    /// the previous save in place
     pub fn save(&self, save_file_path: String) -> Result<(), String> {
-        let file = std::fs::File::create(save_file_path).map_err(|e| e.to_string())?;
-        let mut writer = std::io::BufWriter::new(file);
-        serde_json::to_writer(&mut writer, &self.persistent_state).map_err(|e| e.to_string())?;
+        let metadata = SaveMetadata::new(self.simulated_time(), String::new());
+        self.write_save(Path::new(&save_file_path), &metadata)
+    }
+
+    /// save to a named slot, with a summary and optionally a thumbnail (e.g.
+    /// from screenshot) to show in a list of saves
+    pub fn save_slot(
+        &self,
+        slots: &SaveSlots,
+        slot: &str,
+        summary: String,
+        thumbnail: Option<&SurfaceRef>,
+    ) -> Result<(), String> {
+        let mut metadata = SaveMetadata::new(self.simulated_time(), summary);
+        if let Some(frame) = thumbnail {
+            metadata.set_thumbnail(frame, SaveSlots::THUMBNAIL_WIDTH)?;
+        }
+        self.write_save(&slots.path(slot)?, &metadata)
+    }
+
+    /// from then on, run autosaves according to the policy into slots named
+    /// autosave_1, autosave_2, and so on. the files are written on a
+    /// background thread. errors are given to on_error rather than ending
+    /// run.\
+    /// no autosaves are made while replaying
+    pub fn enable_autosave<F>(
+        &mut self,
+        slots: SaveSlots,
+        policy: AutosavePolicy,
+        on_error: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(String) + 'static,
+    {
+        self.disable_autosave();
+        let autosaver = Autosaver::new(slots, policy, self.tick(), Box::new(on_error))?;
+        self.autosaver = Some(autosaver);
         Ok(())
     }
 
-    /// reads save file and replaces only persistent entities member\
+    /// waits for any autosave still being written
+    pub fn disable_autosave(&mut self) {
+        if let Some(mut autosaver) = self.autosaver.take() {
+            autosaver.flush();
+        }
+    }
+
+    /// None if autosave isn't enabled
+    pub fn autosave_policy(&self) -> Option<&AutosavePolicy> {
+        self.autosaver.as_ref().map(|autosaver| &autosaver.policy)
+    }
+
+    pub fn autosave_policy_mut(&mut self) -> Option<&mut AutosavePolicy> {
+        self.autosaver
+            .as_mut()
+            .map(|autosaver| &mut autosaver.policy)
+    }
+
+    /// autosave now, regardless of the policy, into the next autosave slot.
+    /// does nothing if autosave isn't enabled
+    pub fn autosave(&mut self) {
+        if self.autosaver.is_none() {
+            return;
+        }
+        let (format, tick) = (self.save_format, self.tick());
+        let body = save::serialize(&self.save_envelope(), format.codec);
+        let metadata = SaveMetadata::new(self.simulated_time(), "autosave".to_owned());
+        let autosaver = self.autosaver.as_mut().unwrap();
+        match body {
+            Ok(body) => autosaver.submit(body, format, metadata, tick),
+            Err(e) => autosaver.report_error(e),
+        }
+    }
+
+    // an autosave made by run. skipped while replaying, or if the previous
+    // one is still being written
+    fn run_autosave(&mut self) {
+        let busy = self.autosaver.as_ref().is_none_or(|a| a.is_busy());
+        if !busy && self.replaying.is_none() {
+            self.autosave();
+        }
+    }
+
+    // report finished autosaves, and autosave if the interval has passed
+    fn poll_autosave(&mut self) {
+        let tick = self.tick();
+        let autosaver = match &mut self.autosaver {
+            Some(autosaver) => autosaver,
+            None => return,
+        };
+        autosaver.poll();
+        // e.g. an earlier save was loaded
+        if tick < autosaver.last_tick {
+            autosaver.last_tick = tick;
+        }
+        if autosaver.is_due(tick, self.tick_duration) {
+            self.run_autosave();
+        }
+    }
+
+    /// same as load, from a named slot
+    pub fn load_slot(&mut self, slots: &SaveSlots, slot: &str) -> Result<PathBuf, String> {
+        let path = slots.path(slot)?;
+        self.load(path.to_string_lossy().into_owned())
+    }
+
+    /// reads save file and replaces only persistent entities member. entities
+    /// saved with older schema versions are migrated first, see `save_schema_mut`.
+    /// if the file is missing, corrupt, or fails to migrate or deserialize, the
+    /// newest backup which loads is loaded instead.
+    /// returns the path of the file that was loaded, so that the player can
+    /// be told when it was a backup.\
+    /// the replaced entities are given on_despawn, then the loaded ones
+    /// on_spawn, then every entity on_loaded.\
     /// consider first calling clear to also remove volatile entities
-    pub fn load(&mut self, path: String) -> Result<(), String> {
-        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
-        let reader = std::io::BufReader::new(file);
-        let mut incoming_persistent_state: PersistentStateTemp =
-            serde_json::from_reader(reader).map_err(|e| e.to_string())?;
-        incoming_persistent_state.replace(&mut self.persistent_state)?;
-        Ok(())
+    pub fn load(&mut self, path: String) -> Result<PathBuf, String> {
+        let (mut incoming_persistent_state, loaded) =
+            save::read_with_fallback(Path::new(&path), self.save_backups, |bytes| {
+                self.save_schema.read::<PersistentStateTemp>(bytes)
+            })?;
+        let replaced = incoming_persistent_state.replace(&mut self.persistent_state)?;
+        self.contacts.clear();
+        self.messages.get_mut().clear();
+        replaced.iter().for_each(|entity| {
+            entity.on_despawn(self);
+        });
+        for entities in self.persistent_state.persistent_layers.values() {
+            entities.iter().for_each(|entity| entity.on_spawn(self));
+        }
+        self.loaded();
+        Ok(loaded)
+    }
+
+    // on_loaded for every entity
+    fn loaded(&self) {
+        for entities in self.persistent_state.persistent_layers.values() {
+            entities.iter().for_each(|entity| entity.on_loaded(self));
+        }
+        for entities in self.volatile_layers.values() {
+            entities.iter().for_each(|entity| entity.on_loaded(self));
+        }
     }
 
-    /// clear only persistent entities
+    /// clear only persistent entities. each is given on_despawn, after all of
+    /// them have been removed
     pub fn clear_persistent(&mut self) {
         debug_assert_layers_rc_sanity!(&self.persistent_state.persistent_layers);
-        self.persistent_state
+        let despawned: Vec<PersistentEntity> = self
+            .persistent_state
             .persistent_layers
             .values_mut()
-            .for_each(|v| v.clear());
+            .flat_map(std::mem::take)
+            .collect();
+        self.persistent_state.id_lookup.clear();
+        self.contacts.clear();
+        self.messages.get_mut().clear();
+        despawned.iter().for_each(|entity| {
+            entity.on_despawn(self);
+        });
     }
 
     /// clears all entities
     pub fn clear(&mut self) {
         self.clear_persistent();
+        self.clear_volatile();
+    }
+
+    // same as clear_persistent, for volatile entities
+    fn clear_volatile(&mut self) {
         debug_assert_layers_rc_sanity!(&self.volatile_layers);
-        self.volatile_layers.values_mut().for_each(|v| v.clear());
+        let despawned: Vec<VolatileEntity> = self
+            .volatile_layers
+            .values_mut()
+            .flat_map(std::mem::take)
+            .collect();
+        despawned.iter().for_each(|entity| {
+            entity.on_despawn(self);
+        });
     }
 
     /// spawn a volatile entity to a render layer
@@ -676,18 +1762,35 @@ impl GameState {
                 layer
             ))
             .push(VolatileEntity(Rc::new(Cell::new(Some(e)))));
+        let entity = self.volatile_layers.get(&layer).unwrap().last().unwrap();
+        entity.on_spawn(self);
     }
 
-    /// spawn a persistent entity to a render layer
-    pub fn spawn_persistent(&mut self, e: PersistentSpawn, layer: &'static str) {
+    /// spawn a persistent entity to a render layer. returns its newly
+    /// allocated id
+    pub fn spawn_persistent(&mut self, e: PersistentSpawn, layer: &'static str) -> EntityId {
+        assert!(
+            self.persistent_state.persistent_layers.contains_key(&layer),
+            "Spawn of persistent to unregistered layer: {}",
+            layer
+        );
+        let entity = self.persistent_state.new_entity(e, layer);
+        let id = entity.id();
         self.persistent_state
             .persistent_layers
             .get_mut(&layer)
-            .expect(&format!(
-                "Spawn of persistent to unregistered layer: {}",
-                layer
-            ))
-            .push(PersistentEntity(Rc::new(Cell::new(Some(e)))));
+            .unwrap()
+            .push(entity.clone());
+        entity.on_spawn(self);
+        id
+    }
+
+    /// look up a persistent entity by id. None if it isn't part of the game
+    pub fn get_persistent(&self, id: EntityId) -> Option<PersistentRef> {
+        self.persistent_state
+            .id_lookup
+            .get(&id)
+            .map(|(_, entity)| entity.clone())
     }
 
     pub fn get_volatiles(&self, layer: &'static str) -> &Vec<VolatileEntity> {
@@ -703,164 +1806,2057 @@ impl GameState {
             .expect(&format!("get_persistents on unregistered layer: {}", layer))
     }
 
-    /// event_handler closure should return false (dead) or err only if run should return. it handles sdl2 events\
-    /// post_render_hook is a render function over top of the game. it may return an error string which also causes run to return\
-    pub fn run<EventHandler,PostRenderHook>(&mut self, event_handler: EventHandler, post_render_hook: PostRenderHook) -> Result<(), String>
+    /// start recording the events given to the event handler in run. the
+    /// current persistent entities are kept as the starting point of the
+    /// replay, along with what else changes how the game is stepped (pause,
+    /// time scale, tick rate, gravity and the layers' settings and order).\
+    /// volatile entities aren't part of that, so they are cleared here;
+    /// otherwise the replay wouldn't begin from the same state. each is given
+    /// on_despawn (their spawns are dropped), then the persistent entities are
+    /// given on_loaded, the same as when the replay starts. messages that
+    /// haven't been delivered are dropped
+    pub fn start_recording(&mut self) -> Result<(), String> {
+        let start = self.save_value()?;
+        self.clear_volatile();
+        self.contacts.clear();
+        self.messages.get_mut().clear();
+        self.loaded();
+        self.recording = Some(Replay::new(start, self.replay_settings()));
+        Ok(())
+    }
+
+    fn replay_settings(&self) -> ReplaySettings {
+        ReplaySettings {
+            paused: self.paused,
+            pending_advance: self.pending_advance,
+            time_scale: self.time_scale,
+            tick_duration: self.tick_duration,
+            gravity: self.gravity,
+            layers: self
+                .layer_names
+                .iter()
+                .map(|layer| (layer.to_string(), self.layer_settings[layer].clone()))
+                .collect(),
+        }
+    }
+
+    // the recorded layers must be registered. others are put after them
+    fn apply_replay_settings(&mut self, settings: &ReplaySettings) -> Result<(), String> {
+        let mut layers: Vec<&'static str> = Vec::new();
+        for (name, _) in settings.layers.iter() {
+            match self.layer_names.iter().find(|layer| **layer == name) {
+                Some(layer) => layers.push(layer),
+                None => return Err(format!("replay of unregistered layer: {}", name)),
+            }
+        }
+        self.paused = settings.paused;
+        self.pending_advance = settings.pending_advance;
+        self.time_scale = settings.time_scale;
+        self.tick_duration = settings.tick_duration;
+        self.gravity = settings.gravity;
+        for (layer, (_, layer_settings)) in layers.iter().zip(settings.layers.iter()) {
+            self.layer_settings.insert(*layer, layer_settings.clone());
+        }
+        let rest: Vec<&'static str> = self
+            .layer_names
+            .iter()
+            .copied()
+            .filter(|layer| !layers.contains(layer))
+            .collect();
+        layers.extend(rest);
+        self.layer_names = layers;
+        Ok(())
+    }
+
+    /// returns the recording, or None if there wasn't one in progress
+    pub fn stop_recording(&mut self) -> Option<Replay> {
+        self.recording.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// clear all entities and restore the state from the start of the replay.
+    /// then, run gives the recorded events to its event handler at the same
+    /// ticks as they were recorded, instead of live input. live quit events
+    /// are still given so the window can be closed.\
+    /// any recording in progress is stopped. replaying stops on its own after
+    /// the last event.\
+    /// the layers which were registered when recording must still be. nothing
+    /// is changed if they aren't
+    pub fn start_replay(&mut self, replay: Replay) -> Result<(), String> {
+        let mut incoming_persistent_state = self.read_save_value(replay.start)?;
+        self.apply_replay_settings(&replay.settings)?;
+        self.clear();
+        // anything posted by on_despawn during the clear
+        self.messages.get_mut().clear();
+        // nothing is replaced, since the clear removed it. entities aren't
+        // given on_spawn, since they weren't when the recording started
+        incoming_persistent_state.replace(&mut self.persistent_state)?;
+        self.loaded();
+        self.recording = None;
+        self.replaying = Some(replay.events.into());
+        Ok(())
+    }
+
+    pub fn stop_replay(&mut self) {
+        self.replaying = None;
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replaying.is_some()
+    }
+
+    /// while replaying, give the recorded events up to and including the
+    /// current tick to the event handler. same return as the event handler
+    fn replay_events<EventHandler>(&mut self, event_handler: &EventHandler) -> Result<bool, String>
     where
-    EventHandler: Fn(&mut Self, &sdl2::event::Event) -> Result<bool, String>,
-    PostRenderHook: Fn(&mut WindowCanvas)
+        EventHandler: Fn(&mut Self, &sdl2::event::Event) -> Result<bool, String>,
     {
-        let seconds_per_frame = std::time::Duration::from_secs_f32(1f32 / Self::GOAL_FPS);
-        'outer: loop {
-            let start = std::time::Instant::now();
-            while let Some(event) = self.event_pump.poll_event() {
-                // forward all event to the closure
-                match event_handler(self, &event) {
-                    Ok(alive) => {
-                        if !alive {
-                            break 'outer; // closure requested finish
-                        }
-                    }
-                    Err(e) => return Err(e), // propagate error
-                };
+        let window_id = self.canvas.window().id();
+        // the tick is re-read each time since the event handler may load
+        while let Some((_, e)) = self.replaying.as_mut().and_then(|events| {
+            let due = events
+                .front()
+                .is_some_and(|(tick, _)| *tick <= self.persistent_state.tick);
+            if due {
+                events.pop_front()
+            } else {
+                None
             }
+        }) {
+            if !event_handler(self, &e.to_event(window_id))? {
+                return Ok(false);
+            }
+        }
 
-            // generate rates
-            self.persistent_state
-                .persistent_layers
-                .values()
-                .for_each(|entities| {
-                    entities.iter().for_each(|entity| {
-                        entity.generate_rate(&self);
-                    })
-                });
-            self.volatile_layers.values().for_each(|entities| {
-                entities.iter().for_each(|entity| {
-                    entity.generate_rate(&self);
-                })
-            });
+        if matches!(&self.replaying, Some(events) if events.is_empty()) {
+            self.replaying = None;
+        }
+        Ok(true)
+    }
 
-            // apply rates
-            self.persistent_state
-                .persistent_layers
-                .values()
-                .for_each(|entities| {
-                    entities.iter().for_each(|entity| {
-                        entity.apply_rate();
-                    })
-                });
-            self.volatile_layers.values().for_each(|entities| {
-                entities.iter().for_each(|entity| {
-                    entity.apply_rate();
-                })
-            });
+    /// all persistent entities in a layer of type T.\
+    /// entities which are currently taken are skipped. for example, within
+    /// generate_rate this doesn't include the entity that is generating its
+    /// rate
+    pub fn persistents_of<T: Persistent>(
+        &self,
+        layer: &'static str,
+    ) -> impl Iterator<Item = PersistentView<'_, T>> {
+        self.get_persistents(layer)
+            .iter()
+            .filter_map(PersistentView::new)
+    }
 
-            // apply spawns - despawn
-            let mut persistent_spawn: Vec<(&'static str, Vec<PersistentSpawn>)> = Vec::new();
-            let mut volatile_spawn: Vec<(&'static str, Vec<VolatileSpawn>)> = Vec::new();
-            self.persistent_state
-                .persistent_layers
-                .values_mut()
-                .for_each(|layer| {
-                    let len = layer.len();
-                    for i in (0..len).rev() {
-                        let e = &layer[i];
-                        let mut r = e.apply_spawns();
-                        if let LivelinessStatus::Despawn = r.alive {
-                            debug_assert!(
-                                Rc::strong_count(&e.0) == 1,
-                                "only the game state is allowed strong references to entities. \
-                            inter-entity references should be weak. this possibly leaks"
-                            );
-                            layer.remove(i);
-                        }
-                        persistent_spawn.append(&mut r.persistent_spawns);
-                        volatile_spawn.append(&mut r.volatile_spawns);
-                    }
-                });
-            self.volatile_layers.values_mut().for_each(|layer| {
-                let len = layer.len();
-                for i in (0..len).rev() {
-                    let e = &layer[i];
-                    let mut r = e.apply_spawns();
-                    if let LivelinessStatus::Despawn = r.alive {
-                        debug_assert!(
-                            Rc::strong_count(&e.0) == 1,
-                            "only the game state is allowed strong references to entities. \
-                        inter-entity references should be weak. this possibly leaks"
-                        );
-                        layer.remove(i);
-                    }
-                    volatile_spawn.append(&mut r.volatile_spawns);
+    /// same as persistents_of, but over every layer in render order
+    pub fn all_persistents_of<T: Persistent>(&self) -> impl Iterator<Item = PersistentView<'_, T>> {
+        self.layer_names
+            .iter()
+            .flat_map(|layer| self.persistents_of::<T>(layer))
+    }
+
+    /// all volatile entities in a layer of type T. see persistents_of
+    pub fn volatiles_of<T: Volatile>(
+        &self,
+        layer: &'static str,
+    ) -> impl Iterator<Item = VolatileView<'_, T>> {
+        self.get_volatiles(layer)
+            .iter()
+            .filter_map(VolatileView::new)
+    }
+
+    /// same as volatiles_of, but over every layer in render order
+    pub fn all_volatiles_of<T: Volatile>(&self) -> impl Iterator<Item = VolatileView<'_, T>> {
+        self.layer_names
+            .iter()
+            .flat_map(|layer| self.volatiles_of::<T>(layer))
+    }
+
+    /// maintain an index of entity positions for query_rect and query_radius.
+    /// it's built from `Persistent::bounds` and `Volatile::bounds` at the start
+    /// of each step, so queries in generate_rate see where entities are as of
+    /// that step.\
+    /// cell_size is in world units, and should be around the size of a
+    /// typical entity
+    pub fn enable_spatial_index(&mut self, cell_size: f32) {
+        self.spatial_index = Some(SpatialHash::new(cell_size));
+        self.rebuild_spatial_index();
+    }
+
+    pub fn disable_spatial_index(&mut self) {
+        self.spatial_index = None;
+    }
+
+    pub fn is_spatial_index_enabled(&self) -> bool {
+        self.spatial_index.is_some()
+    }
+
+    fn rebuild_spatial_index(&mut self) {
+        let index = match &mut self.spatial_index {
+            Some(index) => index,
+            None => return,
+        };
+        index.clear();
+        for (layer, entities) in self.persistent_state.persistent_layers.iter() {
+            for entity in entities.iter() {
+                if let Some(bounds) = entity.bounds() {
+                    let entity = SpatialEntity::Persistent(entity.downgrade());
+                    index.insert(
+                        bounds,
+                        SpatialEntry {
+                            layer,
+                            bounds,
+                            entity,
+                        },
+                    );
                 }
-            });
+            }
+        }
+        for (layer, entities) in self.volatile_layers.iter() {
+            for entity in entities.iter() {
+                if let Some(bounds) = entity.bounds() {
+                    let entity = SpatialEntity::Volatile(Rc::downgrade(&entity.0));
+                    index.insert(
+                        bounds,
+                        SpatialEntry {
+                            layer,
+                            bounds,
+                            entity,
+                        },
+                    );
+                }
+            }
+        }
+    }
 
-            // new spawns
-            for s in persistent_spawn {
-                let layer = self
-                    .persistent_state
-                    .persistent_layers
-                    .get_mut(&s.0)
-                    .expect(&format!(
-                        "Entity created persistent spawn for unregistered layer: {}",
-                        &s.0
-                    ));
-                let mut spawned_as_entities =
-                    s.1.into_iter()
-                        .map(Some)
-                        .map(Cell::new)
-                        .map(Rc::new)
-                        .map(|rc| PersistentEntity(rc))
-                        .collect();
-                layer.append(&mut spawned_as_entities);
-            }
-            for s in volatile_spawn {
-                let layer = self.volatile_layers.get_mut(&s.0).expect(&format!(
-                    "Entity created volatile spawn for unregistered layer: {}",
-                    &s.0
-                ));
-                let mut spawned_as_entities =
-                    s.1.into_iter()
-                        .map(Some)
-                        .map(Cell::new)
-                        .map(Rc::new)
-                        .map(|rc| VolatileEntity(rc))
-                        .collect();
-                layer.append(&mut spawned_as_entities);
-            }
-
-            self.canvas.set_draw_color(sdl2::pixels::Color::BLACK);
-            self.canvas.clear();
-
-            // render all
-            self.layer_names.iter().for_each(|layer_name| {
-                self.volatile_layers
-                    .get(*layer_name)
-                    .unwrap()
-                    .iter()
-                    .for_each(|entity| {
-                        entity.render(&mut self.canvas);
+    /// entities with bounds that intersect the rectangle.\
+    /// panics if the spatial index isn't enabled
+    pub fn query_rect(&self, rect: Aabb) -> Vec<SpatialEntry> {
+        self.spatial_index
+            .as_ref()
+            .expect("query_rect without spatial index enabled")
+            .query_rect(rect)
+            .into_iter()
+            .map(|(_, entry)| entry.clone())
+            .collect()
+    }
+
+    /// entities with bounds within radius of the center.\
+    /// panics if the spatial index isn't enabled
+    pub fn query_radius(&self, center: (f32, f32), radius: f32) -> Vec<SpatialEntry> {
+        self.spatial_index
+            .as_ref()
+            .expect("query_radius without spatial index enabled")
+            .query_radius(center, radius)
+            .into_iter()
+            .map(|(_, entry)| entry.clone())
+            .collect()
+    }
+
+    /// colliders are bucketed into a grid of this cell size (world units)
+    /// before being checked against each other. it should be around the size
+    /// of a typical collider
+    pub fn set_collision_cell_size(&mut self, cell_size: f32) {
+        assert!(cell_size > 0f32, "collision cell size must be positive");
+        self.collision_cell_size = cell_size;
+    }
+
+    pub fn collision_cell_size(&self) -> f32 {
+        self.collision_cell_size
+    }
+
+    // every pair of overlapping colliders where either side detects the other.
+    // ordered by layer then position in layer, the same each run
+    fn find_contacts(&self) -> Vec<Contact> {
+        let mut sides: Vec<ContactSide> = Vec::new();
+        for (layer, entities) in self.persistent_state.persistent_layers.iter() {
+            for entity in entities.iter() {
+                if let Some(collider) = entity.collider() {
+                    sides.push(ContactSide {
+                        key: ContactKey::Persistent(entity.id()),
+                        layer,
+                        entity: SpatialEntity::Persistent(entity.downgrade()),
+                        collider,
                     });
-                self.persistent_state
-                    .persistent_layers
-                    .get(*layer_name)
-                    .unwrap()
-                    .iter()
-                    .for_each(|entity| {
-                        entity.render(&mut self.canvas);
+                }
+            }
+        }
+        for (layer, entities) in self.volatile_layers.iter() {
+            for entity in entities.iter() {
+                if let Some(collider) = entity.collider() {
+                    sides.push(ContactSide {
+                        key: ContactKey::Volatile(Rc::as_ptr(&entity.0) as usize),
+                        layer,
+                        entity: SpatialEntity::Volatile(Rc::downgrade(&entity.0)),
+                        collider,
                     });
-            });
+                }
+            }
+        }
 
-            post_render_hook(&mut self.canvas);
+        let mut contacts = Vec::new();
+        if sides.len() < 2 {
+            return contacts;
+        }
+        let mut grid = SpatialHash::new(self.collision_cell_size);
+        for (i, side) in sides.iter().enumerate() {
+            grid.insert(side.collider.shape.bounds(), i);
+        }
+        for (i, a) in sides.iter().enumerate() {
+            for (_, j) in grid.query_rect(a.collider.shape.bounds()) {
+                // each pair once
+                if *j <= i {
+                    continue;
+                }
+                let b = &sides[*j];
+                if !a.collider.detects(&b.collider) && !b.collider.detects(&a.collider) {
+                    continue;
+                }
+                if a.collider.shape.overlaps(&b.collider.shape) {
+                    contacts.push(Contact {
+                        a: a.clone(),
+                        b: b.clone(),
+                    });
+                }
+            }
+        }
+        contacts
+    }
 
-            self.canvas.present();
+    // on_collision for receiver, if it detects other and isn't paused
+    fn deliver_collision(
+        &self,
+        receiver: &ContactSide,
+        other: &ContactSide,
+        phase: CollisionPhase,
+    ) {
+        if !self.is_layer_updated(receiver.layer) || !receiver.collider.detects(&other.collider) {
+            return;
+        }
+        receiver.entity.on_collision(
+            self,
+            &Collision {
+                phase,
+                other: other.entity.clone(),
+                other_layer: other.layer,
+                other_collider: other.collider,
+            },
+        );
+    }
 
-            let stop = std::time::Instant::now();
-            let duration = stop - start;
-            if duration < seconds_per_frame {
-                std::thread::sleep(seconds_per_frame - duration);
+    // find overlaps and give enter / stay / exit to both sides of each.
+    // returns the new contacts
+    fn update_collisions(&self) -> Vec<Contact> {
+        let previous = &self.contacts;
+        let current = self.find_contacts();
+        let previous_keys: HashSet<(ContactKey, ContactKey)> =
+            previous.iter().map(Contact::keys).collect();
+        let current_keys: HashSet<(ContactKey, ContactKey)> =
+            current.iter().map(Contact::keys).collect();
+
+        for contact in current.iter() {
+            let phase = if previous_keys.contains(&contact.keys()) {
+                CollisionPhase::Stay
+            } else {
+                CollisionPhase::Enter
+            };
+            self.deliver_collision(&contact.a, &contact.b, phase);
+            self.deliver_collision(&contact.b, &contact.a, phase);
+        }
+        for contact in previous.iter() {
+            if !current_keys.contains(&contact.keys()) {
+                self.deliver_collision(&contact.a, &contact.b, CollisionPhase::Exit);
+                self.deliver_collision(&contact.b, &contact.a, CollisionPhase::Exit);
             }
         }
-        Ok(())
+        current
+    }
+
+    /// queue a message, which is given to its recipients' on_message at the
+    /// start of the next step, in the order it was posted. entities in paused
+    /// layers don't receive messages.\
+    /// messages aren't saved; pending messages are dropped on load or clear
+    pub fn post<T: Any>(&self, recipient: Recipient, payload: T) {
+        self.messages.borrow_mut().push(Message::new(
+            recipient,
+            self.persistent_state.tick,
+            Box::new(payload),
+        ));
+    }
+
+    /// post to a single persistent entity. dropped if it has despawned
+    pub fn send_to<T: Any>(&self, to: &PersistentRef, payload: T) {
+        if let Some(id) = to.id() {
+            self.post(Recipient::Entity(id), payload);
+        }
+    }
+
+    /// post to every persistent entity of type E
+    pub fn send_to_type<E: Persistent, T: Any>(&self, payload: T) {
+        self.post(Recipient::of_type::<E>(), payload);
+    }
+
+    /// post to every volatile entity of type E
+    pub fn send_to_volatile_type<E: Volatile, T: Any>(&self, payload: T) {
+        self.post(Recipient::of_volatile_type::<E>(), payload);
+    }
+
+    // each message is given to its recipients in updated layers, in the order
+    // posted. entities are only gone through to find the recipients of
+    // messages sent to a type or channel, once for all of them
+    fn deliver_messages(&self, messages: &[Message]) {
+        let mut of_type: HashMap<TypeId, Vec<LayerEntity>> = HashMap::new();
+        let mut subscribed: HashMap<&'static str, Vec<LayerEntity>> = HashMap::new();
+        for message in messages.iter() {
+            match message.recipient {
+                Recipient::Entity(_) => {}
+                Recipient::Type(t) => {
+                    of_type.entry(t).or_default();
+                }
+                Recipient::Channel(channel) => {
+                    subscribed.entry(channel).or_default();
+                }
+            }
+        }
+        if !of_type.is_empty() || !subscribed.is_empty() {
+            let persistents = self
+                .persistent_state
+                .persistent_layers
+                .iter()
+                .filter(|(layer, _)| self.is_layer_updated(layer))
+                .flat_map(|(_, entities)| entities.iter().map(LayerEntity::Persistent));
+            let volatiles = self
+                .volatile_layers
+                .iter()
+                .filter(|(layer, _)| self.is_layer_updated(layer))
+                .flat_map(|(_, entities)| entities.iter().map(LayerEntity::Volatile));
+            for entity in persistents.chain(volatiles) {
+                if let Some(recipients) = of_type.get_mut(&entity.concrete_type()) {
+                    recipients.push(entity);
+                }
+                for (channel, recipients) in subscribed.iter_mut() {
+                    if entity.subscribed(channel) {
+                        recipients.push(entity);
+                    }
+                }
+            }
+        }
+
+        for message in messages.iter() {
+            match message.recipient {
+                Recipient::Entity(id) => {
+                    let recipient = self.persistent_state.id_lookup.get(&id);
+                    if let Some((layer, entity)) = recipient {
+                        if self.is_layer_updated(layer) {
+                            if let Some(mut entity) = entity.guard().ok() {
+                                entity.on_message(self, message);
+                            }
+                        }
+                    }
+                }
+                Recipient::Type(t) => of_type[&t]
+                    .iter()
+                    .for_each(|entity| entity.on_message(self, message)),
+                Recipient::Channel(channel) => subscribed[channel]
+                    .iter()
+                    .for_each(|entity| entity.on_message(self, message)),
+            }
+        }
+    }
+
+    /// post to every entity subscribed to the channel
+    pub fn broadcast<T: Any>(&self, channel: &'static str, payload: T) {
+        self.post(Recipient::Channel(channel), payload);
+    }
+
+    pub fn gravity(&self) -> (f32, f32) {
+        self.gravity
+    }
+
+    /// acceleration applied to every rigid body (scaled by its gravity_scale),
+    /// in world units per second squared. no gravity by default
+    pub fn set_gravity(&mut self, gravity: (f32, f32)) {
+        self.gravity = gravity;
+    }
+
+    // bounce apart rigid bodies whose colliders overlap and detect each other
+    fn resolve_contacts(&self) {
+        for contact in self.contacts.iter() {
+            let (a, b) = (&contact.a, &contact.b);
+            if !self.is_layer_updated(a.layer) || !self.is_layer_updated(b.layer) {
+                continue;
+            }
+            if !a.collider.detects(&b.collider) || !b.collider.detects(&a.collider) {
+                continue;
+            }
+            let (ax, ay) = a.collider.shape.bounds().center();
+            let (bx, by) = b.collider.shape.bounds().center();
+            let distance = ((bx - ax).powi(2) + (by - ay).powi(2)).sqrt();
+            if distance == 0f32 {
+                // no direction to push in
+                continue;
+            }
+            let normal = ((bx - ax) / distance, (by - ay) / distance);
+            a.entity.with_rigid_body(|a_body| {
+                b.entity.with_rigid_body(|b_body| {
+                    physics::resolve_contact(a_body, b_body, normal);
+                })
+            });
+        }
+    }
+
+    /// event_handler closure should return false (dead) or err only if run should return. it handles sdl2 events\
+    /// post_render_hook is a render function over top of the game. it may return an error string which also causes run to return\
+    /// the simulation is stepped at the tick rate regardless of how fast
+    /// frames are rendered. rendering and the post render hook continue while
+    /// paused.\
+    /// however run returns, the on quit autosave is made (see
+    /// `AutosavePolicy::on_quit`) and autosaves being written are waited for
+    pub fn run<EventHandler,PostRenderHook>(&mut self, event_handler: EventHandler, post_render_hook: PostRenderHook) -> Result<(), String>
+    where
+    EventHandler: Fn(&mut Self, &sdl2::event::Event) -> Result<bool, String>,
+    PostRenderHook: Fn(&mut dyn Renderer) -> Result<(), String>
+    {
+        let result = self.run_frames(&event_handler, &post_render_hook);
+
+        if self.autosave_policy().is_some_and(|p| p.on_quit) && self.replaying.is_none() {
+            self.autosave();
+        }
+        // so that the process doesn't exit partway through writing
+        if let Some(autosaver) = &mut self.autosaver {
+            autosaver.flush();
+        }
+        result
+    }
+
+    // the loop of run, until the event handler ends it or there's an error
+    fn run_frames<EventHandler, PostRenderHook>(
+        &mut self,
+        event_handler: &EventHandler,
+        post_render_hook: &PostRenderHook,
+    ) -> Result<(), String>
+    where
+        EventHandler: Fn(&mut Self, &sdl2::event::Event) -> Result<bool, String>,
+        PostRenderHook: Fn(&mut dyn Renderer) -> Result<(), String>,
+    {
+        // real time which has passed but hasn't yet been simulated
+        let mut accumulator = std::time::Duration::ZERO;
+        let mut previous = std::time::Instant::now();
+        loop {
+            let now = std::time::Instant::now();
+            let elapsed = (now - previous).min(Self::MAX_FRAME_DURATION);
+            previous = now;
+
+            while let Some(event) = self.poll_event() {
+                if self.replaying.is_some() {
+                    // live input is ignored while replaying, apart from quit
+                    if !matches!(event, sdl2::event::Event::Quit { .. }) {
+                        continue;
+                    }
+                } else if let Some(recording) = &mut self.recording {
+                    recording.record(self.persistent_state.tick, &event);
+                }
+
+                // forward all event to the closure. an error is propagated
+                if !event_handler(self, &event)? {
+                    return Ok(()); // closure requested finish
+                }
+
+                if let sdl2::event::Event::Window {
+                    win_event: sdl2::event::WindowEvent::FocusLost,
+                    ..
+                } = event
+                {
+                    if self.autosave_policy().is_some_and(|p| p.on_focus_lost) {
+                        self.run_autosave();
+                    }
+                }
+            }
+
+            // recorded events are given before each tick, at the same points
+            // as when they were recorded
+            if !self.replay_events(event_handler)? {
+                return Ok(());
+            }
+
+            if self.paused {
+                // on resume, continue from the same point between ticks
+                while self.pending_advance != 0 {
+                    self.step();
+                    self.pending_advance -= 1;
+                    if !self.replay_events(event_handler)? {
+                        return Ok(());
+                    }
+                }
+            } else {
+                accumulator += elapsed.mul_f32(self.time_scale);
+                // tick duration is re-read each iteration since the event
+                // handler may have changed it
+                while accumulator >= self.tick_duration {
+                    self.step();
+                    accumulator -= self.tick_duration;
+                    if !self.replay_events(event_handler)? {
+                        return Ok(());
+                    }
+                }
+            }
+
+            self.poll_autosave();
+
+            let alpha = accumulator.as_secs_f32() / self.tick_duration.as_secs_f32();
+            self.render(post_render_hook, alpha)?;
+
+            // otherwise without vsync the loop spins, using a whole core
+            if let Some(min_frame_duration) = self.min_frame_duration {
+                let spent = now.elapsed();
+                if spent < min_frame_duration {
+                    std::thread::sleep(min_frame_duration - spent);
+                }
+            }
+        }
+    }
+
+    /// number of simulation ticks per second
+    pub fn tick_rate(&self) -> f32 {
+        1f32 / self.tick_duration.as_secs_f32()
+    }
+
+    /// set the number of simulation ticks per second. this is independent of
+    /// the rate at which frames are rendered
+    pub fn set_tick_rate(&mut self, ticks_per_second: f32) {
+        assert!(ticks_per_second > 0f32, "tick rate must be positive");
+        self.tick_duration = std::time::Duration::from_secs_f32(1f32 / ticks_per_second);
+    }
+
+    /// most frames per second rendered by run. None if uncapped
+    pub fn max_frame_rate(&self) -> Option<f32> {
+        self.min_frame_duration.map(|d| 1f32 / d.as_secs_f32())
+    }
+
+    /// None to render frames as fast as possible. the simulation still steps
+    /// at the tick rate either way
+    pub fn set_max_frame_rate(&mut self, frames_per_second: Option<f32>) {
+        if let Some(frames_per_second) = frames_per_second {
+            assert!(frames_per_second > 0f32, "frame rate must be positive");
+        }
+        self.min_frame_duration =
+            frames_per_second.map(|f| std::time::Duration::from_secs_f32(1f32 / f));
+    }
+
+    /// stop run from stepping the simulation. rendering continues
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// undo pause. any advances which haven't happened yet are dropped
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.pending_advance = 0;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// while paused, have run step the simulation by this many more ticks
+    /// (e.g. 1 for frame by frame advance). does nothing if not paused
+    pub fn advance(&mut self, ticks: u64) {
+        if self.paused {
+            self.pending_advance += ticks;
+        }
+    }
+
+    /// multiplier on the speed of the simulation in run. e.g. 0.5 is slow
+    /// motion. the tick duration is unchanged; ticks just happen less often
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        assert!(time_scale >= 0f32, "time scale can't be negative");
+        self.time_scale = time_scale;
+    }
+
+    /// a paused layer's entities aren't updated by step but are still
+    /// rendered. e.g. the game world under a pause menu.\
+    /// same as setting `LayerSettings::update_enabled`. panics if the layer
+    /// isn't registered
+    pub fn set_layer_paused(&mut self, layer: &'static str, paused: bool) {
+        self.layer_settings_mut(layer)
+            .unwrap_or_else(|| panic!("pause of unregistered layer: {}", layer))
+            .update_enabled = !paused;
+    }
+
+    /// panics if the layer isn't registered
+    pub fn is_layer_paused(&self, layer: &'static str) -> bool {
+        !self
+            .layer_settings(layer)
+            .unwrap_or_else(|| panic!("pause of unregistered layer: {}", layer))
+            .update_enabled
+    }
+
+    // false for layers which are paused or not registered
+    fn is_layer_updated(&self, layer: &str) -> bool {
+        self.layer_settings
+            .get(layer)
+            .is_some_and(|settings| settings.update_enabled)
+    }
+
+    /// the first camera
+    pub fn camera(&self) -> &Camera {
+        self.cameras.first().expect("no cameras")
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        self.cameras.first_mut().expect("no cameras")
+    }
+
+    /// every camera, in the order they're drawn. there is one covering the
+    /// whole window by default; add more with separate viewports for split
+    /// screen
+    pub fn cameras(&self) -> &[Camera] {
+        &self.cameras
+    }
+
+    pub fn cameras_mut(&mut self) -> &mut Vec<Camera> {
+        &mut self.cameras
+    }
+
+    /// the transform that a camera currently draws world space layers with
+    /// (without parallax). e.g. to find the world position of the mouse.\
+    /// an error if the window's size can't be found
+    pub fn camera_transform(&self, camera: usize) -> Result<Transform, String> {
+        let window_size = self.canvas.output_size()?;
+        Ok(self.cameras[camera].transform(window_size, 1f32))
+    }
+
+    /// registered layers, in render order
+    pub fn layers(&self) -> &[&'static str] {
+        &self.layer_names
+    }
+
+    pub fn has_layer(&self, layer: &str) -> bool {
+        self.layer_settings.contains_key(layer)
+    }
+
+    /// register a layer, rendered over top of all existing layers
+    pub fn add_layer(&mut self, layer: &'static str, settings: LayerSettings) {
+        self.insert_layer(self.layer_names.len(), layer, settings);
+    }
+
+    /// register a layer at a position in the render order
+    pub fn insert_layer(&mut self, index: usize, layer: &'static str, settings: LayerSettings) {
+        assert!(
+            !self.has_layer(layer),
+            "layer already registered: {}",
+            layer
+        );
+        self.layer_names.insert(index, layer);
+        self.layer_settings.insert(layer, settings);
+        self.persistent_state
+            .persistent_layers
+            .insert(layer, Vec::new());
+        self.volatile_layers.insert(layer, Vec::new());
+    }
+
+    /// unregister a layer. its entities are despawned, receiving on_despawn
+    /// (their spawns are dropped)
+    pub fn remove_layer(&mut self, layer: &'static str) {
+        assert!(
+            self.has_layer(layer),
+            "remove of unregistered layer: {}",
+            layer
+        );
+        let (persistent, volatile) = self.take_layer_entities(layer);
+        self.layer_names.retain(|name| *name != layer);
+        self.layer_settings.remove(layer);
+        self.persistent_state.persistent_layers.remove(layer);
+        self.volatile_layers.remove(layer);
+        persistent.iter().for_each(|entity| {
+            entity.on_despawn(self);
+        });
+        volatile.iter().for_each(|entity| {
+            entity.on_despawn(self);
+        });
+    }
+
+    /// move a registered layer to a position in the render order. an error
+    /// if the layer isn't registered or index isn't less than the number of
+    /// layers, leaving the order as it was
+    pub fn move_layer(&mut self, layer: &'static str, index: usize) -> Result<(), String> {
+        let current = self
+            .layer_names
+            .iter()
+            .position(|name| *name == layer)
+            .ok_or_else(|| format!("move of unregistered layer: {}", layer))?;
+        // the layer is removed first, so the last position is len - 1
+        if index >= self.layer_names.len() {
+            return Err(format!(
+                "move of layer {} to {}, past the last of {} layers",
+                layer,
+                index,
+                self.layer_names.len()
+            ));
+        }
+        self.layer_names.remove(current);
+        self.layer_names.insert(index, layer);
+        Ok(())
+    }
+
+    /// None if the layer isn't registered
+    pub fn layer_settings(&self, layer: &'static str) -> Option<&LayerSettings> {
+        self.layer_settings.get(layer)
+    }
+
+    pub fn layer_settings_mut(&mut self, layer: &'static str) -> Option<&mut LayerSettings> {
+        self.layer_settings.get_mut(layer)
+    }
+
+    /// despawn every entity in layers with clear_on_scene_change, e.g. when
+    /// moving from the menu to a level. each is given on_despawn after all of
+    /// them have been removed (their spawns are dropped)
+    pub fn change_scene(&mut self) {
+        let cleared: Vec<&'static str> = self
+            .layer_names
+            .iter()
+            .copied()
+            .filter(|layer| self.layer_settings[layer].clear_on_scene_change)
+            .collect();
+        let mut persistent: Vec<PersistentEntity> = Vec::new();
+        let mut volatile: Vec<VolatileEntity> = Vec::new();
+        for layer in cleared {
+            let (mut p, mut v) = self.take_layer_entities(layer);
+            persistent.append(&mut p);
+            volatile.append(&mut v);
+        }
+        persistent.iter().for_each(|entity| {
+            entity.on_despawn(self);
+        });
+        volatile.iter().for_each(|entity| {
+            entity.on_despawn(self);
+        });
+    }
+
+    // empties the layer, leaving it registered
+    fn take_layer_entities(
+        &mut self,
+        layer: &'static str,
+    ) -> (Vec<PersistentEntity>, Vec<VolatileEntity>) {
+        let persistent = std::mem::take(
+            self.persistent_state
+                .persistent_layers
+                .get_mut(layer)
+                .unwrap(),
+        );
+        let volatile = std::mem::take(self.volatile_layers.get_mut(layer).unwrap());
+        debug_assert!(
+            persistent
+                .iter()
+                .all(|entity| Rc::strong_count(&entity.0) == 1)
+                && volatile
+                    .iter()
+                    .all(|entity| Rc::strong_count(&entity.0) == 1),
+            "only the game state is allowed strong references to entities. \
+            inter-entity references should be weak. this possibly leaks"
+        );
+        for entity in persistent.iter() {
+            self.persistent_state.id_lookup.remove(&entity.id());
+        }
+        (persistent, volatile)
+    }
+
+    /// the game's random number generator. entities should use this instead
+    /// of `rand::thread_rng` so that a run is reproducible from a save file; its
+    /// position in the sequence is saved and loaded with the persistent
+    /// entities.\
+    /// the returned borrow must be dropped before this is called again
+    pub fn rng(&self) -> std::cell::RefMut<'_, GameRng> {
+        self.persistent_state.rng.borrow_mut()
+    }
+
+    /// restart the random number generator from a known seed. it is otherwise
+    /// seeded from entropy when the game state is created
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.persistent_state.rng = RefCell::new(GameRng::seed_from_u64(seed));
+    }
+
+    /// simulated time between ticks
+    pub fn tick_duration(&self) -> std::time::Duration {
+        self.tick_duration
+    }
+
+    /// number of ticks which have been fully simulated. this is saved and
+    /// loaded with the persistent entities.\
+    /// during a step, this is the index of the tick currently being simulated
+    pub fn tick(&self) -> u64 {
+        self.persistent_state.tick
+    }
+
+    /// total simulated time over all ticks. this is saved and loaded with the
+    /// persistent entities
+    pub fn simulated_time(&self) -> std::time::Duration {
+        self.persistent_state.simulated_time
+    }
+
+    /// next sdl event, if any. used by run; only needed when driving the
+    /// game state from another loop with step and render
+    pub fn poll_event(&mut self) -> Option<sdl2::event::Event> {
+        self.event_pump.poll_event()
+    }
+
+    /// step the simulation by n frames. see step
+    pub fn step_n(&mut self, n: usize) {
+        for _ in 0..n {
+            self.step();
+        }
+    }
+
+    /// the rate and spawn phases for every entity, for a single frame.\
+    /// this doesn't sleep, poll events or render; that's left to the caller
+    /// (e.g. run). it steps even if paused, but paused layers are skipped
+    pub fn step(&mut self) {
+        self.rebuild_spatial_index();
+
+        // entities in paused layers skip all phases below
+        let updated = |layer_name: &&'static str| {
+            self.layer_settings
+                .get(*layer_name)
+                .is_some_and(|settings| settings.update_enabled)
+        };
+
+        // deliver messages from the previous step. any posted now are
+        // delivered next step
+        let messages = self.messages.take();
+        if !messages.is_empty() {
+            self.deliver_messages(&messages);
+        }
+
+        // generate rates
+        self.persistent_state
+            .persistent_layers
+            .iter()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, entities)| {
+                entities.iter().for_each(|entity| {
+                    entity.generate_rate(&self);
+                })
+            });
+        self.volatile_layers
+            .iter()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, entities)| {
+                entities.iter().for_each(|entity| {
+                    entity.generate_rate(&self);
+                })
+            });
+
+        // collisions, from the state before rates are applied
+        self.contacts = self.update_collisions();
+
+        // apply rates
+        self.persistent_state
+            .persistent_layers
+            .iter()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, entities)| {
+                entities.iter().for_each(|entity| {
+                    entity.apply_rate();
+                })
+            });
+        self.volatile_layers
+            .iter()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, entities)| {
+                entities.iter().for_each(|entity| {
+                    entity.apply_rate();
+                })
+            });
+
+        // physics
+        self.resolve_contacts();
+        let dt = self.tick_duration.as_secs_f32();
+        self.persistent_state
+            .persistent_layers
+            .iter()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, entities)| {
+                entities.iter().for_each(|entity| {
+                    entity.integrate(dt, self.gravity);
+                })
+            });
+        self.volatile_layers
+            .iter()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, entities)| {
+                entities.iter().for_each(|entity| {
+                    entity.integrate(dt, self.gravity);
+                })
+            });
+
+        // apply spawns - despawn
+        let mut persistent_spawn: Vec<(&'static str, Vec<PersistentSpawn>)> = Vec::new();
+        let mut volatile_spawn: Vec<(&'static str, Vec<VolatileSpawn>)> = Vec::new();
+        let mut persistent_despawned: Vec<PersistentEntity> = Vec::new();
+        let mut volatile_despawned: Vec<VolatileEntity> = Vec::new();
+        self.persistent_state
+            .persistent_layers
+            .iter_mut()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, layer)| {
+                let len = layer.len();
+                for i in (0..len).rev() {
+                    let e = &layer[i];
+                    let mut r = e.apply_spawns();
+                    if let LivelinessStatus::Despawn = r.alive {
+                        debug_assert!(
+                            Rc::strong_count(&e.0) == 1,
+                            "only the game state is allowed strong references to entities. \
+                        inter-entity references should be weak. this possibly leaks"
+                        );
+                        self.persistent_state.id_lookup.remove(&e.id());
+                        persistent_despawned.push(layer.remove(i));
+                    }
+                    persistent_spawn.append(&mut r.persistent_spawns);
+                    volatile_spawn.append(&mut r.volatile_spawns);
+                }
+            });
+        self.volatile_layers
+            .iter_mut()
+            .filter(|(layer_name, _)| updated(layer_name))
+            .for_each(|(_, layer)| {
+                let len = layer.len();
+                for i in (0..len).rev() {
+                    let e = &layer[i];
+                    let mut r = e.apply_spawns();
+                    if let LivelinessStatus::Despawn = r.alive {
+                        debug_assert!(
+                            Rc::strong_count(&e.0) == 1,
+                            "only the game state is allowed strong references to entities. \
+                        inter-entity references should be weak. this possibly leaks"
+                        );
+                        volatile_despawned.push(layer.remove(i));
+                    }
+                    volatile_spawn.append(&mut r.volatile_spawns);
+                }
+            });
+
+        // despawn hooks, once the entities are out of the game state
+        for e in persistent_despawned {
+            volatile_spawn.append(&mut e.on_despawn(&self));
+        }
+        for e in volatile_despawned {
+            volatile_spawn.append(&mut e.on_despawn(&self));
+        }
+
+        // new spawns
+        let mut persistent_spawned: Vec<PersistentEntity> = Vec::new();
+        let mut volatile_spawned: Vec<VolatileEntity> = Vec::new();
+        for s in persistent_spawn {
+            let mut spawned_as_entities: Vec<PersistentEntity> =
+                s.1.into_iter()
+                    .map(|e| self.persistent_state.new_entity(e, s.0))
+                    .collect();
+            let layer = self
+                .persistent_state
+                .persistent_layers
+                .get_mut(&s.0)
+                .expect(&format!(
+                    "Entity created persistent spawn for unregistered layer: {}",
+                    &s.0
+                ));
+            persistent_spawned.extend(spawned_as_entities.iter().map(|e| e.clone()));
+            layer.append(&mut spawned_as_entities);
+        }
+        for s in volatile_spawn {
+            let layer = self.volatile_layers.get_mut(&s.0).expect(&format!(
+                "Entity created volatile spawn for unregistered layer: {}",
+                &s.0
+            ));
+            let mut spawned_as_entities =
+                s.1.into_iter()
+                    .map(Some)
+                    .map(Cell::new)
+                    .map(Rc::new)
+                    .map(|rc| VolatileEntity(rc))
+                    .collect::<Vec<VolatileEntity>>();
+            volatile_spawned.extend(
+                spawned_as_entities
+                    .iter()
+                    .map(|e| VolatileEntity(e.0.clone())),
+            );
+            layer.append(&mut spawned_as_entities);
+        }
+        persistent_spawned.iter().for_each(|e| e.on_spawn(&self));
+        volatile_spawned.iter().for_each(|e| e.on_spawn(&self));
+
+        self.persistent_state.tick += 1;
+        self.persistent_state.simulated_time += self.tick_duration;
+    }
+
+    /// draw all entities, then the post render hook over top, and present.\
+    /// alpha is given to entities; see RenderInfo
+    pub fn render<PostRenderHook>(
+        &mut self,
+        post_render_hook: &PostRenderHook,
+        alpha: f32,
+    ) -> Result<(), String>
+    where
+        PostRenderHook: Fn(&mut dyn Renderer) -> Result<(), String>,
+    {
+        draw_layers(
+            &self.layer_names,
+            &self.layer_settings,
+            &self.cameras,
+            &self.volatile_layers,
+            &self.persistent_state.persistent_layers,
+            &mut self.canvas,
+            alpha,
+        )?;
+        post_render_hook(&mut self.canvas)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    /// the frame most recently drawn to the window. some renderers discard it
+    /// once presented, so if this comes out blank take it in the post render
+    /// hook of render instead
+    pub fn screenshot(&self) -> Result<Surface<'static>, String> {
+        let (w, h) = self.canvas.output_size()?;
+        let pixels = self.canvas.read_pixels(None, PixelFormatEnum::RGBA32)?;
+        let mut surface = Surface::new(w, h, PixelFormatEnum::RGBA32)?;
+        let pitch = surface.pitch() as usize;
+        let row = w as usize * 4;
+        surface.with_lock_mut(|bytes| {
+            for y in 0..h as usize {
+                bytes[y * pitch..y * pitch + row].copy_from_slice(&pixels[y * row..(y + 1) * row]);
+            }
+        });
+        Ok(surface)
+    }
+
+    /// draw all entities to a renderer other than the window, e.g. an
+    /// offscreen one. cameras' viewports are relative to its output size
+    pub fn render_to(&self, renderer: &mut dyn Renderer, alpha: f32) -> Result<(), String> {
+        draw_layers(
+            &self.layer_names,
+            &self.layer_settings,
+            &self.cameras,
+            &self.volatile_layers,
+            &self.persistent_state.persistent_layers,
+            renderer,
+            alpha,
+        )
+    }
+}
+
+// clear, then draw every visible layer in order
+fn draw_layers(
+    layer_names: &[&'static str],
+    layer_settings: &HashMap<&'static str, LayerSettings>,
+    cameras: &[Camera],
+    volatile_layers: &BTreeMap<&'static str, Vec<VolatileEntity>>,
+    persistent_layers: &BTreeMap<&'static str, Vec<PersistentEntity>>,
+    renderer: &mut dyn Renderer,
+    alpha: f32,
+) -> Result<(), String> {
+    renderer.set_draw_color(sdl2::pixels::Color::BLACK);
+    renderer.clear();
+    let window_size = renderer.output_size()?;
+
+    for layer_name in layer_names.iter() {
+        let settings = &layer_settings[layer_name];
+        if !settings.visible {
+            continue;
+        }
+        let entities = layer_render_order(
+            volatile_layers.get(layer_name).unwrap(),
+            persistent_layers.get(layer_name).unwrap(),
+            settings.sorted,
+        );
+        if settings.world_space {
+            // once through each camera
+            for camera in cameras.iter() {
+                let transform = camera.transform(window_size, settings.parallax);
+                renderer.set_viewport(Some(transform.viewport));
+                renderer.set_transform(transform);
+                let info = RenderInfo { alpha, transform };
+                entities.iter().for_each(|entity| {
+                    entity.render(renderer, &info);
+                });
+            }
+        } else {
+            let transform = Transform::identity(window_size);
+            renderer.set_viewport(None);
+            renderer.set_transform(transform);
+            let info = RenderInfo { alpha, transform };
+            entities.iter().for_each(|entity| {
+                entity.render(renderer, &info);
+            });
+        }
+    }
+    renderer.set_viewport(None);
+    renderer.set_transform(Transform::identity(window_size));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{DrawCall, RecordingRenderer};
+    use rand::Rng;
+    use sdl2::rect::Rect;
+    use std::sync::Mutex;
+
+    // sdl can only be initialized on one thread at a time, so tests which
+    // create a game state take turns
+    static SDL: Mutex<()> = Mutex::new(());
+
+    thread_local! {
+        // hooks called on the test entities, in order
+        static LOG: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn log(entry: String) {
+        LOG.with(|log| log.borrow_mut().push(entry));
+    }
+
+    fn take_log() -> Vec<String> {
+        LOG.with(|log| log.take())
+    }
+
+    fn with_state(layers: &'static [&'static str], f: impl FnOnce(&mut GameState)) {
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        take_log();
+        let mut state = GameState::new_headless((64, 48), layers).unwrap();
+        f(&mut state);
+    }
+
+    // counts its steps and keeps each number it draws from the game's rng
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Counter {
+        name: String,
+        steps: u64,
+        rolls: Vec<u32>,
+        // despawned once it has been stepped this many times
+        lifetime: Option<u64>,
+        sort_key: f32,
+    }
+
+    impl Counter {
+        fn new(name: &str) -> Box<Self> {
+            Box::new(Self {
+                name: name.to_string(),
+                steps: 0,
+                rolls: Vec::new(),
+                lifetime: None,
+                sort_key: 0f32,
+            })
+        }
+    }
+
+    #[typetag::serde]
+    impl Persistent for Counter {
+        fn generate_rate(&mut self, state: &GameState) {
+            self.rolls.push(state.rng().gen());
+        }
+
+        fn apply_rate(&mut self) {
+            self.steps += 1;
+        }
+
+        fn apply_spawns(&self) -> PersistentSpawnChanges {
+            PersistentSpawnChanges {
+                alive: LivelinessStatus::new(self.lifetime.is_none_or(|l| self.steps < l)),
+                volatile_spawns: Vec::new(),
+                persistent_spawns: Vec::new(),
+            }
+        }
+
+        // identified by the x position of a 1x1 rect
+        fn render(&self, renderer: &mut dyn Renderer, _info: &RenderInfo) {
+            let x = self.name.parse().unwrap_or(-1);
+            renderer.fill_rect(Rect::new(x, 0, 1, 1)).unwrap();
+        }
+
+        fn sort_key(&self) -> f32 {
+            self.sort_key
+        }
+
+        fn on_spawn(&mut self, _state: &GameState) {
+            log(format!("spawn {}", self.name));
+        }
+
+        fn on_despawn(&mut self, _state: &GameState) -> Vec<(&'static str, Vec<VolatileSpawn>)> {
+            log(format!("despawn {}", self.name));
+            Vec::new()
+        }
+
+        fn on_loaded(&mut self, _state: &GameState) {
+            log(format!("loaded {}", self.name));
+        }
+
+        fn on_message(&mut self, _state: &GameState, message: &Message) {
+            log(format!(
+                "{} got {}",
+                self.name,
+                message.payload::<u32>().unwrap()
+            ));
+        }
+
+        fn subscribed(&self, channel: &str) -> bool {
+            channel == "counters" || channel == "all"
+        }
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Other;
+
+    #[typetag::serde]
+    impl Persistent for Other {
+        fn generate_rate(&mut self, _state: &GameState) {}
+
+        fn apply_rate(&mut self) {}
+
+        fn render(&self, _renderer: &mut dyn Renderer, _info: &RenderInfo) {}
+    }
+
+    struct Marker {
+        x: i32,
+        sort_key: f32,
+    }
+
+    impl Volatile for Marker {
+        fn generate_rate(&mut self, _state: &GameState) {}
+
+        fn apply_rate(&mut self) {}
+
+        fn render(&self, renderer: &mut dyn Renderer, _info: &RenderInfo) {
+            renderer.fill_rect(Rect::new(self.x, 0, 1, 1)).unwrap();
+        }
+
+        fn sort_key(&self) -> f32 {
+            self.sort_key
+        }
+
+        fn on_spawn(&mut self, _state: &GameState) {
+            log(format!("spawn marker {}", self.x));
+        }
+
+        fn on_despawn(&mut self, _state: &GameState) -> Vec<(&'static str, Vec<VolatileSpawn>)> {
+            log(format!("despawn marker {}", self.x));
+            Vec::new()
+        }
+
+        fn on_message(&mut self, _state: &GameState, message: &Message) {
+            log(format!(
+                "marker {} got {}",
+                self.x,
+                message.payload::<u32>().unwrap()
+            ));
+        }
+
+        fn subscribed(&self, channel: &str) -> bool {
+            channel == "all"
+        }
+    }
+
+    fn marker(x: i32, sort_key: f32) -> Box<Marker> {
+        Box::new(Marker { x, sort_key })
+    }
+
+    fn counters(state: &GameState) -> Vec<(String, u64)> {
+        state
+            .all_persistents_of::<Counter>()
+            .map(|c| (c.name.clone(), c.steps))
+            .collect()
+    }
+
+    // x of each 1x1 rect drawn, in order
+    fn drawn(state: &GameState) -> Vec<i32> {
+        let mut renderer = RecordingRenderer::new((64, 48));
+        state.render_to(&mut renderer, 0f32).unwrap();
+        renderer
+            .take_calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                DrawCall::FillRect(rect, _) => Some(rect.x()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // runs until the post render hook has been called for this many frames
+    fn run_frames(state: &mut GameState, frames: usize) {
+        run_frames_with(state, frames, |_, _| Ok(true));
+    }
+
+    fn run_frames_with<EventHandler>(
+        state: &mut GameState,
+        frames: usize,
+        event_handler: EventHandler,
+    ) where
+        EventHandler: Fn(&mut GameState, &sdl2::event::Event) -> Result<bool, String>,
+    {
+        state.set_max_frame_rate(None);
+        let rendered = Cell::new(0);
+        let result = state.run(event_handler, |_| {
+            rendered.set(rendered.get() + 1);
+            if rendered.get() == frames {
+                Err("done".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result, Err("done".to_string()));
+    }
+
+    #[test]
+    fn step_updates_entities_once_per_tick() {
+        with_state(&["world"], |state| {
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.step();
+            state.step_n(3);
+            assert_eq!(state.tick(), 4);
+            assert_eq!(state.simulated_time(), state.tick_duration() * 4);
+            assert_eq!(counters(state), [("a".to_string(), 4)]);
+        });
+    }
+
+    #[test]
+    fn tick_rate_sets_the_simulated_time_per_step() {
+        with_state(&["world"], |state| {
+            state.set_tick_rate(50f32);
+            assert_eq!(state.tick_duration(), std::time::Duration::from_millis(20));
+            state.step_n(5);
+            assert_eq!(
+                state.simulated_time(),
+                std::time::Duration::from_millis(100)
+            );
+        });
+    }
+
+    #[test]
+    fn paused_run_only_steps_the_advanced_ticks() {
+        with_state(&["world"], |state| {
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.pause();
+            state.advance(3);
+            run_frames(state, 5);
+            assert!(state.is_paused());
+            assert_eq!(state.tick(), 3);
+            assert_eq!(counters(state), [("a".to_string(), 3)]);
+        });
+    }
+
+    #[test]
+    fn resume_drops_advances_which_havent_happened() {
+        with_state(&["world"], |state| {
+            state.advance(2);
+            state.pause();
+            state.advance(3);
+            state.resume();
+            state.set_time_scale(0f32);
+            run_frames(state, 3);
+            assert_eq!(state.tick(), 0);
+        });
+    }
+
+    #[test]
+    fn zero_time_scale_stops_run_stepping() {
+        with_state(&["world"], |state| {
+            state.set_time_scale(0f32);
+            run_frames(state, 5);
+            assert_eq!(state.tick(), 0);
+            // step is still up to the caller
+            state.step();
+            assert_eq!(state.tick(), 1);
+        });
+    }
+
+    #[test]
+    fn paused_layer_is_not_updated() {
+        with_state(&["world", "hud"], |state| {
+            state.spawn_persistent(Counter::new("world"), "world");
+            state.spawn_persistent(Counter::new("hud"), "hud");
+            state.set_layer_paused("world", true);
+            assert!(state.is_layer_paused("world"));
+            state.step_n(2);
+            state.set_layer_paused("world", false);
+            state.step();
+            assert_eq!(
+                counters(state),
+                [("world".to_string(), 1), ("hud".to_string(), 3)]
+            );
+        });
+    }
+
+    #[test]
+    fn rng_continues_the_same_after_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save").to_string_lossy().into_owned();
+        with_state(&["world"], |state| {
+            state.seed_rng(7);
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.step_n(2);
+            state.save(path.clone()).unwrap();
+            state.step_n(3);
+            let rolls: Vec<u32> = state
+                .all_persistents_of::<Counter>()
+                .next()
+                .unwrap()
+                .rolls
+                .clone();
+
+            state.load(path.clone()).unwrap();
+            assert_eq!(state.tick(), 2);
+            state.step_n(3);
+            let replayed = state
+                .all_persistents_of::<Counter>()
+                .next()
+                .unwrap()
+                .rolls
+                .clone();
+            assert_eq!(replayed, rolls);
+            assert_ne!(rolls[2], rolls[3]);
+        });
+    }
+
+    fn add_steps(e: &mut serde_json::Value) -> Result<(), String> {
+        let steps = e["steps"].as_u64().ok_or("no steps")?;
+        e["steps"] = (steps + 40).into();
+        Ok(())
+    }
+
+    #[test]
+    fn load_falls_back_when_the_save_does_not_deserialize() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        let path_str = path.to_string_lossy().into_owned();
+        with_state(&["world"], |state| {
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.save(path_str.clone()).unwrap();
+            state.step();
+            state.save(path_str.clone()).unwrap();
+
+            // a valid file, but not a save of this game
+            let other = serde_json::json!({"header": state.save_schema().header(), "state": 1});
+            let bytes = save::encode(&other, SaveFormat::COMPACT, &SaveMetadata::default());
+            std::fs::write(&path, bytes.unwrap()).unwrap();
+            let loaded = state.load(path_str.clone()).unwrap();
+            assert_eq!(loaded, save::backup_path(&path, 1));
+            assert_eq!(counters(state), [("a".to_string(), 0)]);
+        });
+    }
+
+    // autosave with only the given triggers. errors fail the test
+    fn enable_autosave(state: &mut GameState, dir: &Path, policy: AutosavePolicy) -> SaveSlots {
+        let slots = SaveSlots::in_dir(dir).unwrap();
+        state
+            .enable_autosave(slots.clone(), policy, |e| {
+                unreachable!("autosave failed: {}", e)
+            })
+            .unwrap();
+        slots
+    }
+
+    const NO_AUTOSAVE: AutosavePolicy = AutosavePolicy {
+        interval: None,
+        on_focus_lost: false,
+        on_quit: false,
+        slots: 3,
+    };
+
+    fn focus_lost() -> sdl2::event::Event {
+        sdl2::event::Event::Window {
+            timestamp: 0,
+            window_id: 0,
+            win_event: sdl2::event::WindowEvent::FocusLost,
+        }
+    }
+
+    #[test]
+    fn autosave_interval_is_counted_at_the_tick_rate() {
+        let dir = tempfile::tempdir().unwrap();
+        with_state(&["world"], |state| {
+            state.set_tick_rate(10f32);
+            let policy = AutosavePolicy {
+                interval: Some(std::time::Duration::from_secs(1)),
+                ..NO_AUTOSAVE
+            };
+            let slots = enable_autosave(state, dir.path(), policy);
+            let poll = |state: &mut GameState, ticks| {
+                state.step_n(ticks);
+                state.poll_autosave();
+                state.autosaver.as_mut().unwrap().flush();
+            };
+            poll(state, 9);
+            assert!(!slots.exists("autosave_1"));
+            poll(state, 1);
+            assert!(slots.exists("autosave_1"));
+
+            // the same interval is twice the ticks at twice the rate
+            state.set_tick_rate(20f32);
+            poll(state, 19);
+            assert!(!slots.exists("autosave_2"));
+            poll(state, 1);
+            assert!(slots.exists("autosave_2"));
+        });
+    }
+
+    #[test]
+    fn autosave_on_focus_lost() {
+        for on_focus_lost in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            with_state(&["world"], |state| {
+                let policy = AutosavePolicy {
+                    on_focus_lost,
+                    ..NO_AUTOSAVE
+                };
+                let slots = enable_autosave(state, dir.path(), policy);
+                state
+                    ._sdl_context
+                    .event()
+                    .unwrap()
+                    .push_event(focus_lost())
+                    .unwrap();
+                run_frames(state, 1);
+                assert_eq!(slots.exists("autosave_1"), on_focus_lost);
+            });
+        }
+    }
+
+    #[test]
+    fn autosave_on_quit_is_written_before_run_returns() {
+        for on_quit in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            with_state(&["world"], |state| {
+                state.spawn_persistent(Counter::new("a"), "world");
+                let policy = AutosavePolicy {
+                    on_quit,
+                    ..NO_AUTOSAVE
+                };
+                let slots = enable_autosave(state, dir.path(), policy);
+                run_frames(state, 2);
+                // not disabled, so nothing else waited for the write
+                assert!(state.autosave_policy().is_some());
+                assert_eq!(slots.exists("autosave_1"), on_quit);
+                if on_quit {
+                    state.clear();
+                    state.load_slot(&slots, "autosave_1").unwrap();
+                    assert_eq!(counters(state).len(), 1);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn disable_autosave_waits_for_the_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        with_state(&["world"], |state| {
+            let slots = enable_autosave(state, dir.path(), NO_AUTOSAVE);
+            for _ in 0..3 {
+                state.autosave();
+                state.step();
+            }
+            state.disable_autosave();
+            for slot in ["autosave_1", "autosave_2", "autosave_3"] {
+                assert!(slots.metadata(slot).unwrap().is_some(), "{}", slot);
+            }
+            // does nothing once disabled
+            state.autosave();
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+        });
+    }
+
+    #[test]
+    fn saved_entities_are_migrated_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save").to_string_lossy().into_owned();
+        with_state(&["world"], |state| {
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.spawn_persistent(Box::new(Other), "world");
+            state.step();
+            state.step();
+            state.save(path.clone()).unwrap();
+
+            state
+                .save_schema_mut()
+                .add_migration("Counter", 0, add_steps);
+            state.load(path.clone()).unwrap();
+            assert_eq!(counters(state), [("a".to_string(), 42)]);
+            assert_eq!(state.all_persistents_of::<Other>().count(), 1);
+
+            // saved with the current version, so not migrated again
+            state.save(path.clone()).unwrap();
+            state.step();
+            state.load(path.clone()).unwrap();
+            assert_eq!(counters(state), [("a".to_string(), 42)]);
+        });
+    }
+
+    #[test]
+    fn refs_give_access_by_type_until_despawned() {
+        with_state(&["world"], |state| {
+            let id = state.spawn_persistent(Counter::new("a"), "world");
+            let r = state.get_persistent(id).unwrap();
+            assert_eq!(r.id(), Some(id));
+            assert!(matches!(
+                r.with(|c: &Counter| c.steps),
+                PersistentRefAccess::Some(0)
+            ));
+            assert!(matches!(
+                r.with(|_: &Other| ()),
+                PersistentRefAccess::WrongType
+            ));
+            r.with_mut(|c: &mut Counter| c.steps = 5).ok().unwrap();
+
+            let guard = r.guard().ok().unwrap();
+            assert_eq!(guard.id(), id);
+            assert!(matches!(
+                r.with(|c: &Counter| c.steps),
+                PersistentRefAccess::Taken
+            ));
+            assert!(matches!(r.guard(), PersistentGuardResult::Taken));
+            drop(guard);
+            assert_eq!(r.with(|c: &Counter| c.steps).ok(), Some(5));
+
+            state.clear();
+            assert!(state.get_persistent(id).is_none());
+            assert!(matches!(
+                r.with(|c: &Counter| c.steps),
+                PersistentRefAccess::Despawned
+            ));
+            assert!(matches!(r.guard(), PersistentGuardResult::Despawned));
+        });
+    }
+
+    #[test]
+    fn queries_filter_by_type_and_layer() {
+        with_state(&["world", "hud"], |state| {
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.spawn_persistent(Box::new(Other), "world");
+            state.spawn_persistent(Counter::new("b"), "hud");
+            state.spawn_volatile(marker(1, 0f32), "hud");
+
+            let world: Vec<String> = state
+                .persistents_of::<Counter>("world")
+                .map(|c| c.name.clone())
+                .collect();
+            assert_eq!(world, ["a"]);
+            assert_eq!(state.all_persistents_of::<Counter>().count(), 2);
+            assert_eq!(state.all_persistents_of::<Other>().count(), 1);
+            assert_eq!(state.volatiles_of::<Marker>("world").count(), 0);
+            assert_eq!(
+                state
+                    .all_volatiles_of::<Marker>()
+                    .map(|m| m.x)
+                    .collect::<Vec<_>>(),
+                [1]
+            );
+
+            // an entity which is taken is skipped
+            let id = state
+                .persistents_of::<Counter>("world")
+                .next()
+                .unwrap()
+                .id();
+            let _guard = state.get_persistent(id).unwrap().guard().ok().unwrap();
+            assert_eq!(state.all_persistents_of::<Counter>().count(), 1);
+        });
+    }
+
+    #[test]
+    fn hooks_are_called_on_spawn_despawn_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save").to_string_lossy().into_owned();
+        with_state(&["world"], |state| {
+            let mut short = Counter::new("short");
+            short.lifetime = Some(1);
+            state.spawn_persistent(short, "world");
+            state.spawn_volatile(marker(1, 0f32), "world");
+            assert_eq!(take_log(), ["spawn short", "spawn marker 1"]);
+
+            state.step();
+            assert_eq!(take_log(), ["despawn short"]);
+
+            state.spawn_persistent(Counter::new("saved"), "world");
+            state.save(path.clone()).unwrap();
+            state.spawn_persistent(Counter::new("unsaved"), "world");
+            take_log();
+            state.load(path.clone()).unwrap();
+            assert_eq!(
+                take_log(),
+                [
+                    "despawn saved",
+                    "despawn unsaved",
+                    "spawn saved",
+                    "loaded saved"
+                ]
+            );
+
+            state.clear();
+            assert_eq!(take_log(), ["despawn saved", "despawn marker 1"]);
+        });
+    }
+
+    #[test]
+    fn messages_are_delivered_next_step_in_the_order_posted() {
+        with_state(&["world", "hud"], |state| {
+            let a = state.spawn_persistent(Counter::new("a"), "world");
+            state.spawn_persistent(Counter::new("b"), "world");
+            state.spawn_persistent(Box::new(Other), "world");
+            state.spawn_volatile(marker(1, 0f32), "world");
+            let a = state.get_persistent(a).unwrap();
+            take_log();
+
+            state.broadcast("all", 1u32);
+            state.send_to(&a, 2u32);
+            state.send_to_type::<Counter, _>(3u32);
+            state.send_to_volatile_type::<Marker, _>(4u32);
+            state.broadcast("counters", 5u32);
+            state.broadcast("nobody", 6u32);
+            assert!(take_log().is_empty());
+            state.step();
+            assert_eq!(
+                take_log(),
+                [
+                    "a got 1",
+                    "b got 1",
+                    "marker 1 got 1",
+                    "a got 2",
+                    "a got 3",
+                    "b got 3",
+                    "marker 1 got 4",
+                    "a got 5",
+                    "b got 5"
+                ]
+            );
+            state.step();
+            assert!(take_log().is_empty());
+        });
+    }
+
+    #[test]
+    fn messages_to_paused_layers_and_despawned_entities_are_dropped() {
+        with_state(&["world", "hud"], |state| {
+            let a = state.spawn_persistent(Counter::new("a"), "world");
+            let b = state.spawn_persistent(Counter::new("b"), "hud");
+            let (a, b) = (
+                state.get_persistent(a).unwrap(),
+                state.get_persistent(b).unwrap(),
+            );
+            state.set_layer_paused("hud", true);
+            take_log();
+            state.send_to(&a, 1u32);
+            state.send_to(&b, 2u32);
+            state.send_to_type::<Counter, _>(3u32);
+            state.step();
+            assert_eq!(take_log(), ["a got 1", "a got 3"]);
+
+            state.send_to(&a, 4u32);
+            state.remove_layer("world");
+            take_log();
+            state.set_layer_paused("hud", false);
+            state.step();
+            assert!(take_log().is_empty());
+        });
+    }
+
+    // advances 3 ticks on N
+    fn advance_on_n(state: &mut GameState, event: &sdl2::event::Event) -> Result<bool, String> {
+        if let sdl2::event::Event::KeyUp {
+            keycode: Some(sdl2::keyboard::Keycode::N),
+            ..
+        } = event
+        {
+            state.advance(3);
+        }
+        Ok(true)
+    }
+
+    #[test]
+    fn replay_restores_the_recorded_state_and_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay").to_string_lossy().into_owned();
+        with_state(&["world", "hud"], |state| {
+            state.seed_rng(3);
+            state.spawn_persistent(Counter::new("world"), "world");
+            state.spawn_persistent(Counter::new("hud"), "hud");
+            state.step();
+            state.pause();
+            state.set_time_scale(0.5f32);
+            state.set_tick_rate(60f32);
+            state.set_gravity((0f32, 2f32));
+            state.set_layer_paused("hud", true);
+            state.move_layer("world", 1).unwrap();
+
+            state.start_recording().unwrap();
+            state
+                ._sdl_context
+                .event()
+                .unwrap()
+                .push_event(sdl2::event::Event::KeyUp {
+                    timestamp: 0,
+                    window_id: 0,
+                    keycode: Some(sdl2::keyboard::Keycode::N),
+                    scancode: None,
+                    keymod: sdl2::keyboard::Mod::NOMOD,
+                    repeat: false,
+                })
+                .unwrap();
+            run_frames_with(state, 3, advance_on_n);
+            let replay = state.stop_recording().unwrap();
+            assert_eq!(replay.len(), 1);
+            let recorded = (
+                state.tick(),
+                state.simulated_time(),
+                counters(state),
+                state
+                    .all_persistents_of::<Counter>()
+                    .next()
+                    .unwrap()
+                    .rolls
+                    .clone(),
+            );
+            assert_eq!(recorded.0, 4);
+            replay.save(path.clone()).unwrap();
+
+            state.resume();
+            state.set_time_scale(1f32);
+            state.set_tick_rate(120f32);
+            state.set_gravity((0f32, 0f32));
+            state.set_layer_paused("hud", false);
+            state.move_layer("world", 0).unwrap();
+            state.step_n(5);
+
+            state
+                .start_replay(Replay::load(path.clone()).unwrap())
+                .unwrap();
+            assert!(state.is_paused());
+            assert_eq!(state.time_scale(), 0.5f32);
+            assert_eq!(state.tick_rate().round(), 60f32);
+            assert_eq!(state.gravity(), (0f32, 2f32));
+            assert!(state.is_layer_paused("hud"));
+            assert_eq!(state.layers(), ["hud", "world"]);
+            assert_eq!(state.tick(), 1);
+
+            run_frames_with(state, 3, advance_on_n);
+            assert!(!state.is_replaying());
+            let replayed = (
+                state.tick(),
+                state.simulated_time(),
+                counters(state),
+                state
+                    .all_persistents_of::<Counter>()
+                    .next()
+                    .unwrap()
+                    .rolls
+                    .clone(),
+            );
+            assert_eq!(replayed, recorded);
+        });
+    }
+
+    #[test]
+    fn replay_of_unregistered_layer_changes_nothing() {
+        with_state(&["world", "hud"], |state| {
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.set_layer_paused("hud", true);
+            state.start_recording().unwrap();
+            let replay = state.stop_recording().unwrap();
+            state.remove_layer("hud");
+            state.set_time_scale(2f32);
+
+            assert!(state.start_replay(replay).is_err());
+            assert!(!state.is_replaying());
+            assert_eq!(state.time_scale(), 2f32);
+            assert_eq!(counters(state), [("a".to_string(), 0)]);
+        });
+    }
+
+    #[test]
+    fn start_recording_despawns_volatiles_through_their_hooks() {
+        with_state(&["world"], |state| {
+            state.spawn_persistent(Counter::new("a"), "world");
+            state.spawn_volatile(marker(1, 0f32), "world");
+            take_log();
+            state.start_recording().unwrap();
+            assert_eq!(take_log(), ["despawn marker 1", "loaded a"]);
+            assert_eq!(state.all_volatiles_of::<Marker>().count(), 0);
+            assert_eq!(counters(state), [("a".to_string(), 0)]);
+        });
+    }
+
+    #[test]
+    fn camera_transform_is_for_the_window_size() {
+        with_state(&["world"], |state| {
+            state.camera_mut().zoom = 2f32;
+            let transform = state.camera_transform(0).unwrap();
+            assert_eq!(transform.world_to_screen((0f32, 0f32)), (32f32, 24f32));
+            assert_eq!(transform.window_to_world((34f32, 24f32)), (1f32, 0f32));
+        });
+    }
+
+    #[test]
+    fn layers_can_be_added_moved_and_removed() {
+        with_state(&["world"], |state| {
+            state.add_layer("hud", LayerSettings::default());
+            state.insert_layer(0, "background", LayerSettings::default());
+            assert_eq!(state.layers(), ["background", "world", "hud"]);
+            assert!(state.has_layer("hud"));
+
+            state.move_layer("background", 2).unwrap();
+            assert_eq!(state.layers(), ["world", "hud", "background"]);
+            assert!(state.move_layer("background", 3).is_err());
+            assert!(state.move_layer("missing", 0).is_err());
+            assert_eq!(state.layers(), ["world", "hud", "background"]);
+            assert!(state.layer_settings("missing").is_none());
+
+            state.spawn_persistent(Counter::new("a"), "hud");
+            state.spawn_volatile(marker(1, 0f32), "hud");
+            take_log();
+            state.remove_layer("hud");
+            assert_eq!(take_log(), ["despawn a", "despawn marker 1"]);
+            assert_eq!(state.layers(), ["world", "background"]);
+            assert!(!state.has_layer("hud"));
+            assert_eq!(state.all_persistents_of::<Counter>().count(), 0);
+        });
+    }
+
+    #[test]
+    fn layers_are_drawn_in_order_and_sorted_within() {
+        with_state(&["back", "front"], |state| {
+            let mut counter = Counter::new("3");
+            counter.sort_key = 1f32;
+            state.spawn_persistent(counter, "front");
+            state.spawn_volatile(marker(2, 2f32), "front");
+            state.spawn_volatile(marker(1, 0f32), "front");
+            // ties keep spawn order
+            state.spawn_volatile(marker(4, 1f32), "front");
+            state.spawn_volatile(marker(0, 5f32), "back");
+            assert_eq!(drawn(state), [0, 1, 4, 3, 2]);
+
+            // unsorted draws volatiles then persistents, each in spawn order
+            state.layer_settings_mut("front").unwrap().sorted = false;
+            assert_eq!(drawn(state), [0, 2, 1, 4, 3]);
+
+            state.move_layer("back", 1).unwrap();
+            state.layer_settings_mut("front").unwrap().visible = false;
+            assert_eq!(drawn(state), [0]);
+        });
+    }
+
+    #[test]
+    fn contact_pair_is_the_same_either_way_round() {
+        let a = ContactKey::Persistent(EntityId(3));
+        let b = ContactKey::Volatile(1);
+        let c = ContactKey::Persistent(EntityId(1));
+        assert_eq!(ContactKey::pair(a, b), ContactKey::pair(b, a));
+        assert_eq!(ContactKey::pair(a, c), (c, a));
+        assert_eq!(ContactKey::pair(c, a), (c, a));
+        let previous: HashSet<_> = [ContactKey::pair(a, b)].into_iter().collect();
+        assert!(previous.contains(&ContactKey::pair(b, a)));
     }
 }
@@ -1,2 +1,11 @@
+pub mod camera;
+pub mod collision;
 pub mod core;
+pub mod message;
+pub mod physics;
+pub mod render;
+pub mod replay;
+pub mod save;
+pub mod snapshot;
+pub mod spatial;
 pub mod ui;
\ No newline at end of file
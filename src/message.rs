@@ -0,0 +1,56 @@
+use std::any::{Any, TypeId};
+
+use crate::core::{EntityId, Persistent, Volatile};
+
+/// who a message is delivered to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Recipient {
+    /// a single persistent entity
+    Entity(EntityId),
+    /// every entity of this concrete type. see `Recipient::of_type` and
+    /// `Recipient::of_volatile_type`
+    Type(TypeId),
+    /// every entity which is subscribed to the channel. see
+    /// `Persistent::subscribed`
+    Channel(&'static str),
+}
+
+impl Recipient {
+    /// every persistent entity of type T
+    pub fn of_type<T: Persistent>() -> Self {
+        Recipient::Type(TypeId::of::<T>())
+    }
+
+    /// every volatile entity of type T
+    pub fn of_volatile_type<T: Volatile>() -> Self {
+        Recipient::Type(TypeId::of::<T>())
+    }
+}
+
+/// posted to the game state during a step, and given to the recipients'
+/// on_message at the start of the next step
+pub struct Message {
+    pub recipient: Recipient,
+    /// the tick that the message was posted in
+    pub tick: u64,
+    payload: Box<dyn Any>,
+}
+
+impl Message {
+    pub(crate) fn new(recipient: Recipient, tick: u64, payload: Box<dyn Any>) -> Self {
+        Self {
+            recipient,
+            tick,
+            payload,
+        }
+    }
+
+    /// None if the payload isn't a T
+    pub fn payload<T: Any>(&self) -> Option<&T> {
+        self.payload.downcast_ref::<T>()
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.payload.is::<T>()
+    }
+}
@@ -0,0 +1,244 @@
+/// opt in physics state for an entity, integrated by the engine each step
+/// after apply_rate. see `Persistent::rigid_body`.\
+/// units are world units and seconds, so behaviour doesn't depend on the tick
+/// rate. it's serializable so it can be a field of a Persistent entity
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct RigidBody {
+    pub x: f32,
+    pub y: f32,
+    pub vx: f32,
+    pub vy: f32,
+    /// f32::INFINITY makes the body immovable by forces, impulses and gravity.
+    /// it still moves at its velocity
+    pub mass: f32,
+    /// proportion of velocity lost per second
+    pub damping: f32,
+    /// multiplied with the game state's gravity
+    pub gravity_scale: f32,
+    /// bounciness of collisions, from 0 (none) to 1 (no energy lost)
+    pub restitution: f32,
+
+    // sum of forces applied since the last integration
+    #[serde(skip)]
+    force: (f32, f32),
+    // position before the last integration. None after load or on creation
+    #[serde(skip)]
+    previous: Option<(f32, f32)>,
+}
+
+impl RigidBody {
+    /// at rest, with mass 1 and no damping
+    pub fn new(x: f32, y: f32) -> Self {
+        Self {
+            x,
+            y,
+            vx: 0f32,
+            vy: 0f32,
+            mass: 1f32,
+            damping: 0f32,
+            gravity_scale: 1f32,
+            restitution: 0f32,
+            force: (0f32, 0f32),
+            previous: None,
+        }
+    }
+
+    pub fn with_velocity(mut self, vx: f32, vy: f32) -> Self {
+        self.vx = vx;
+        self.vy = vy;
+        self
+    }
+
+    pub fn with_mass(mut self, mass: f32) -> Self {
+        self.mass = mass;
+        self
+    }
+
+    pub fn with_damping(mut self, damping: f32) -> Self {
+        self.damping = damping;
+        self
+    }
+
+    pub fn with_gravity_scale(mut self, gravity_scale: f32) -> Self {
+        self.gravity_scale = gravity_scale;
+        self
+    }
+
+    pub fn with_restitution(mut self, restitution: f32) -> Self {
+        self.restitution = restitution;
+        self
+    }
+
+    /// zero for immovable bodies
+    pub fn inverse_mass(&self) -> f32 {
+        if self.mass.is_finite() && self.mass > 0f32 {
+            1f32 / self.mass
+        } else {
+            0f32
+        }
+    }
+
+    /// accumulated and applied over the next integration. typically called
+    /// from generate_rate
+    pub fn apply_force(&mut self, fx: f32, fy: f32) {
+        self.force.0 += fx;
+        self.force.1 += fy;
+    }
+
+    /// instant change in momentum
+    pub fn apply_impulse(&mut self, ix: f32, iy: f32) {
+        let inverse_mass = self.inverse_mass();
+        self.vx += ix * inverse_mass;
+        self.vy += iy * inverse_mass;
+    }
+
+    /// distance moved in the last integration
+    pub fn displacement(&self) -> (f32, f32) {
+        let (px, py) = self.previous.unwrap_or((self.x, self.y));
+        (self.x - px, self.y - py)
+    }
+
+    /// position between the previous integration and the last one. for use
+    /// with `RenderInfo::alpha`
+    pub fn interpolated(&self, alpha: f32) -> (f32, f32) {
+        let (dx, dy) = self.displacement();
+        (self.x - dx * (1f32 - alpha), self.y - dy * (1f32 - alpha))
+    }
+
+    // semi-implicit euler: velocity is updated first, then position from the
+    // new velocity. this stays stable for orbits and springs, where explicit
+    // euler gains energy
+    pub(crate) fn integrate(&mut self, dt: f32, gravity: (f32, f32)) {
+        self.previous = Some((self.x, self.y));
+        let inverse_mass = self.inverse_mass();
+        if inverse_mass != 0f32 {
+            self.vx += (self.force.0 * inverse_mass + gravity.0 * self.gravity_scale) * dt;
+            self.vy += (self.force.1 * inverse_mass + gravity.1 * self.gravity_scale) * dt;
+        }
+        self.force = (0f32, 0f32);
+
+        // the exact solution of dv/dt = -damping * v is exponential decay, but
+        // this approximation never overshoots or reverses direction
+        let damping = 1f32 / (1f32 + self.damping.max(0f32) * dt);
+        self.vx *= damping;
+        self.vy *= damping;
+
+        self.x += self.vx * dt;
+        self.y += self.vy * dt;
+    }
+}
+
+/// bounce two bodies apart along the normal (unit vector, from a to b) if they
+/// are moving towards each other
+pub(crate) fn resolve_contact(a: &mut RigidBody, b: &mut RigidBody, normal: (f32, f32)) {
+    let inverse_mass_sum = a.inverse_mass() + b.inverse_mass();
+    if inverse_mass_sum == 0f32 {
+        return;
+    }
+    let velocity_along_normal = (b.vx - a.vx) * normal.0 + (b.vy - a.vy) * normal.1;
+    if velocity_along_normal >= 0f32 {
+        // separating already
+        return;
+    }
+    let restitution = a.restitution.min(b.restitution);
+    let j = -(1f32 + restitution) * velocity_along_normal / inverse_mass_sum;
+    a.apply_impulse(-j * normal.0, -j * normal.1);
+    b.apply_impulse(j * normal.0, j * normal.1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn integrate_updates_velocity_before_position() {
+        let mut body = RigidBody::new(0f32, 0f32);
+        body.integrate(0.5, (0f32, 10f32));
+        assert!(close(body.vy, 5f32));
+        assert!(close(body.y, 2.5));
+        assert_eq!(body.displacement(), (0f32, body.y));
+    }
+
+    #[test]
+    fn force_is_cleared_after_integration() {
+        let mut body = RigidBody::new(0f32, 0f32).with_mass(2f32);
+        body.apply_force(4f32, 0f32);
+        body.integrate(1f32, (0f32, 0f32));
+        assert!(close(body.vx, 2f32));
+        body.integrate(1f32, (0f32, 0f32));
+        assert!(close(body.vx, 2f32));
+        assert!(close(body.x, 4f32));
+    }
+
+    #[test]
+    fn immovable_body_ignores_forces_and_gravity() {
+        let mut body = RigidBody::new(0f32, 0f32)
+            .with_mass(f32::INFINITY)
+            .with_velocity(1f32, 0f32);
+        body.apply_force(100f32, 100f32);
+        body.apply_impulse(100f32, 100f32);
+        body.integrate(1f32, (0f32, 10f32));
+        assert_eq!((body.vx, body.vy), (1f32, 0f32));
+        assert_eq!((body.x, body.y), (1f32, 0f32));
+    }
+
+    #[test]
+    fn damping_slows_without_reversing() {
+        let mut body = RigidBody::new(0f32, 0f32)
+            .with_velocity(10f32, 0f32)
+            .with_damping(1000f32);
+        for _ in 0..10 {
+            body.integrate(1f32, (0f32, 0f32));
+            assert!(body.vx > 0f32 && body.vx < 10f32);
+        }
+    }
+
+    #[test]
+    fn interpolated_is_between_previous_and_current() {
+        let mut body = RigidBody::new(0f32, 0f32).with_velocity(4f32, 0f32);
+        assert_eq!(body.interpolated(0f32), (0f32, 0f32));
+        body.integrate(1f32, (0f32, 0f32));
+        assert_eq!(body.interpolated(0f32), (0f32, 0f32));
+        assert_eq!(body.interpolated(0.25), (1f32, 0f32));
+        assert_eq!(body.interpolated(1f32), (4f32, 0f32));
+    }
+
+    #[test]
+    fn resolve_contact_conserves_momentum() {
+        let mut a = RigidBody::new(0f32, 0f32)
+            .with_velocity(1f32, 0f32)
+            .with_restitution(1f32);
+        let mut b = RigidBody::new(1f32, 0f32)
+            .with_velocity(-1f32, 0f32)
+            .with_mass(3f32)
+            .with_restitution(1f32);
+        resolve_contact(&mut a, &mut b, (1f32, 0f32));
+        // elastic: momentum and kinetic energy are both kept
+        assert!(close(a.vx + 3f32 * b.vx, -2f32));
+        assert!(close(a.vx * a.vx + 3f32 * b.vx * b.vx, 4f32));
+        assert!(close(a.vx, -2f32) && close(b.vx, 0f32));
+    }
+
+    #[test]
+    fn resolve_contact_ignores_separating_and_immovable_pairs() {
+        let mut a = RigidBody::new(0f32, 0f32).with_velocity(-1f32, 0f32);
+        let mut b = RigidBody::new(1f32, 0f32).with_velocity(1f32, 0f32);
+        resolve_contact(&mut a, &mut b, (1f32, 0f32));
+        assert_eq!((a.vx, b.vx), (-1f32, 1f32));
+
+        let mut wall = RigidBody::new(1f32, 0f32).with_mass(f32::INFINITY);
+        let mut other = wall.clone().with_velocity(-1f32, 0f32);
+        resolve_contact(&mut wall, &mut other, (1f32, 0f32));
+        assert_eq!((wall.vx, other.vx), (0f32, -1f32));
+
+        // inelastic against a wall: stops dead
+        let mut ball = RigidBody::new(0f32, 0f32).with_velocity(2f32, 0f32);
+        resolve_contact(&mut ball, &mut wall, (1f32, 0f32));
+        assert!(close(ball.vx, 0f32));
+        assert_eq!(wall.vx, 0f32);
+    }
+}
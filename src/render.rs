@@ -0,0 +1,616 @@
+use std::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use lru::LruCache;
+use sdl2::{
+    pixels::{Color, PixelFormatEnum},
+    rect::{Point, Rect},
+    render::{Canvas, RenderTarget, Texture},
+    surface::{Surface, SurfaceRef},
+    sys,
+    ttf::Font,
+    video::Window,
+};
+
+use crate::{camera::Transform, spatial::Aabb};
+
+/// what entities and ui components draw to. implemented for an sdl window,
+/// an offscreen surface, and a recorder of draw calls for tests.\
+/// coordinates are pixels relative to the viewport, unless the function takes
+/// world coordinates, which go through the current transform
+pub trait Renderer {
+    fn output_size(&self) -> Result<(u32, u32), String>;
+
+    fn set_draw_color(&mut self, color: Color);
+
+    fn draw_color(&self) -> Color;
+
+    /// fill the entire target with the draw color, ignoring viewport and clip
+    fn clear(&mut self);
+
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), String>;
+
+    /// outline
+    fn draw_rect(&mut self, rect: Rect) -> Result<(), String>;
+
+    fn draw_line(&mut self, start: Point, end: Point) -> Result<(), String>;
+
+    /// the texture must have been created by this renderer's texture creator.
+    /// src None is the whole texture
+    fn copy(&mut self, texture: &Texture, src: Option<Rect>, dst: Rect) -> Result<(), String>;
+
+    /// draw pixels which aren't tied to a renderer. slower than copy, since
+    /// it's uploaded each call
+    fn copy_surface(
+        &mut self,
+        surface: &SurfaceRef,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String>;
+
+    /// same as copy_surface, but renderers with a texture cache (e.g. the
+    /// window's) only upload the surface the first time it's drawn
+    fn copy_cached(
+        &mut self,
+        surface: &CachedSurface,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String> {
+        self.copy_surface(surface.surface(), src, dst)
+    }
+
+    /// draw a line of text with its top left at position. returns where it
+    /// was drawn
+    fn draw_text(
+        &mut self,
+        font: &Font,
+        text: &str,
+        color: Color,
+        position: (i32, i32),
+    ) -> Result<Rect, String> {
+        let surface = font
+            .render(text)
+            .blended(color)
+            .map_err(|e| e.to_string())?;
+        let dst = Rect::new(position.0, position.1, surface.width(), surface.height());
+        self.copy_surface(&surface, None, dst)?;
+        Ok(dst)
+    }
+
+    /// drawing is limited to the rect. None to disable
+    fn set_clip_rect(&mut self, rect: Option<Rect>);
+
+    fn clip_rect(&self) -> Option<Rect>;
+
+    /// the area of the target drawn to. coordinates are relative to its top
+    /// left. None for the whole target
+    fn set_viewport(&mut self, rect: Option<Rect>);
+
+    fn viewport(&self) -> Rect;
+
+    /// world to screen transform used by the world functions below. the game
+    /// state sets it to that of the camera being drawn
+    fn set_transform(&mut self, transform: Transform);
+
+    fn transform(&self) -> Transform;
+
+    /// fill a box given in world coordinates
+    fn fill_world_rect(&mut self, bounds: Aabb) -> Result<(), String> {
+        let rect = self.transform().world_rect(bounds);
+        self.fill_rect(rect)
+    }
+
+    /// outline a box given in world coordinates
+    fn draw_world_rect(&mut self, bounds: Aabb) -> Result<(), String> {
+        let rect = self.transform().world_rect(bounds);
+        self.draw_rect(rect)
+    }
+
+    /// swap the drawn frame onto the target. nothing happens for offscreen
+    /// renderers
+    fn present(&mut self) {}
+}
+
+/// pixels which are drawn over many frames, e.g. rendered text. see
+/// `Renderer::copy_cached`.\
+/// a renderer's cached texture is for a single CachedSurface, so it can't be
+/// changed after it's made; make another instead
+pub struct CachedSurface {
+    id: u64,
+    surface: Surface<'static>,
+}
+
+impl CachedSurface {
+    pub fn new(surface: Surface<'static>) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            surface,
+        }
+    }
+
+    pub fn surface(&self) -> &SurfaceRef {
+        &self.surface
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.surface.size()
+    }
+}
+
+// an sdl texture which isn't tied to the lifetime of a TextureCreator. it must
+// be dropped before the renderer that made it
+struct RawTexture(*mut sys::SDL_Texture);
+
+impl Drop for RawTexture {
+    fn drop(&mut self) {
+        unsafe { sys::SDL_DestroyTexture(self.0) }
+    }
+}
+
+// textures of the cached surfaces drawn most recently, by id
+struct TextureCache {
+    textures: LruCache<u64, RawTexture>,
+}
+
+impl TextureCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            textures: LruCache::new(NonZeroUsize::new(capacity).unwrap()),
+        }
+    }
+
+    fn copy<T: RenderTarget>(
+        &mut self,
+        canvas: &mut Canvas<T>,
+        surface: &CachedSurface,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String> {
+        let texture = match self.textures.get(&surface.id) {
+            Some(texture) => texture.0,
+            None => {
+                let texture = unsafe {
+                    sys::SDL_CreateTextureFromSurface(canvas.raw(), surface.surface.raw())
+                };
+                if texture.is_null() {
+                    return Err(sdl2::get_error());
+                }
+                // the least recently drawn is destroyed if it's full
+                self.textures.push(surface.id, RawTexture(texture));
+                texture
+            }
+        };
+        let src = src.as_ref().map_or(std::ptr::null(), |src| src.raw());
+        if unsafe { sys::SDL_RenderCopy(canvas.raw(), texture, src, dst.raw()) } != 0 {
+            return Err(sdl2::get_error());
+        }
+        Ok(())
+    }
+}
+
+/// renderer over an sdl canvas. derefs to the canvas for anything that isn't
+/// part of Renderer, e.g. texture_creator
+pub struct CanvasRenderer<T: RenderTarget> {
+    // before the canvas, so that the textures are destroyed first
+    textures: Option<TextureCache>,
+    pub canvas: Canvas<T>,
+    transform: Transform,
+}
+
+/// draws to a window
+pub type WindowRenderer = CanvasRenderer<Window>;
+
+/// draws to memory. e.g. for screenshots or golden image tests
+pub type SurfaceRenderer = CanvasRenderer<Surface<'static>>;
+
+impl<T: RenderTarget> CanvasRenderer<T> {
+    /// number of cached surfaces that a window renderer keeps textures for
+    pub const DEFAULT_TEXTURE_CACHE_CAPACITY: usize = 256;
+
+    /// without a texture cache, so copy_cached is the same as copy_surface
+    pub fn new(canvas: Canvas<T>) -> Self {
+        let size = canvas.output_size().unwrap_or((1, 1));
+        Self {
+            textures: None,
+            canvas,
+            transform: Transform::identity(size),
+        }
+    }
+
+    /// keep the textures of the most recently drawn cached surfaces, up to
+    /// capacity (which must be more than 0). for renderers which draw the
+    /// same things every frame, e.g. to a window. ones that draw a single
+    /// frame (e.g. for golden image tests) don't need it
+    pub fn with_texture_cache(mut self, capacity: usize) -> Self {
+        self.textures = Some(TextureCache::new(capacity));
+        self
+    }
+}
+
+impl SurfaceRenderer {
+    /// a blank target of this size, in 32 bit RGBA
+    pub fn new_offscreen(size: (u32, u32)) -> Result<Self, String> {
+        let surface = Surface::new(size.0, size.1, PixelFormatEnum::RGBA32)?;
+        let mut canvas = Canvas::from_surface(surface)?;
+        canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+        Ok(Self::new(canvas))
+    }
+
+    /// what has been drawn so far
+    pub fn surface(&self) -> &SurfaceRef {
+        self.canvas.surface()
+    }
+}
+
+impl<T: RenderTarget> std::ops::Deref for CanvasRenderer<T> {
+    type Target = Canvas<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.canvas
+    }
+}
+
+impl<T: RenderTarget> std::ops::DerefMut for CanvasRenderer<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.canvas
+    }
+}
+
+/// draw a surface to a canvas, of any target type
+pub trait CopySurface {
+    fn copy_surface(
+        &mut self,
+        surface: &SurfaceRef,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String>;
+}
+
+impl<T: RenderTarget> CopySurface for Canvas<T> {
+    fn copy_surface(
+        &mut self,
+        surface: &SurfaceRef,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String> {
+        // sdl2's TextureCreator is only available for each concrete target
+        // type, so the texture is made directly. it's destroyed before
+        // returning, so it can't outlive the renderer
+        unsafe {
+            let texture = sys::SDL_CreateTextureFromSurface(self.raw(), surface.raw());
+            if texture.is_null() {
+                return Err(sdl2::get_error());
+            }
+            let src = src.as_ref().map_or(std::ptr::null(), |src| src.raw());
+            let result = sys::SDL_RenderCopy(self.raw(), texture, src, dst.raw());
+            sys::SDL_DestroyTexture(texture);
+            if result != 0 {
+                return Err(sdl2::get_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: RenderTarget> Renderer for CanvasRenderer<T> {
+    fn output_size(&self) -> Result<(u32, u32), String> {
+        self.canvas.output_size()
+    }
+
+    fn set_draw_color(&mut self, color: Color) {
+        self.canvas.set_draw_color(color);
+    }
+
+    fn draw_color(&self) -> Color {
+        self.canvas.draw_color()
+    }
+
+    fn clear(&mut self) {
+        self.canvas.clear();
+    }
+
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), String> {
+        self.canvas.fill_rect(rect)
+    }
+
+    fn draw_rect(&mut self, rect: Rect) -> Result<(), String> {
+        self.canvas.draw_rect(rect)
+    }
+
+    fn draw_line(&mut self, start: Point, end: Point) -> Result<(), String> {
+        self.canvas.draw_line(start, end)
+    }
+
+    fn copy(&mut self, texture: &Texture, src: Option<Rect>, dst: Rect) -> Result<(), String> {
+        self.canvas.copy(texture, src, dst)
+    }
+
+    fn copy_surface(
+        &mut self,
+        surface: &SurfaceRef,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String> {
+        self.canvas.copy_surface(surface, src, dst)
+    }
+
+    fn copy_cached(
+        &mut self,
+        surface: &CachedSurface,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String> {
+        match &mut self.textures {
+            Some(textures) => textures.copy(&mut self.canvas, surface, src, dst),
+            None => self.canvas.copy_surface(surface.surface(), src, dst),
+        }
+    }
+
+    fn set_clip_rect(&mut self, rect: Option<Rect>) {
+        self.canvas.set_clip_rect(rect);
+    }
+
+    fn clip_rect(&self) -> Option<Rect> {
+        self.canvas.clip_rect()
+    }
+
+    fn set_viewport(&mut self, rect: Option<Rect>) {
+        self.canvas.set_viewport(rect);
+    }
+
+    fn viewport(&self) -> Rect {
+        self.canvas.viewport()
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    fn present(&mut self) {
+        self.canvas.present();
+    }
+}
+
+/// a call made to a RecordingRenderer
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCall {
+    Clear(Color),
+    FillRect(Rect, Color),
+    DrawRect(Rect, Color),
+    DrawLine(Point, Point, Color),
+    /// textures are identified by their size only
+    Copy {
+        texture_size: (u32, u32),
+        src: Option<Rect>,
+        dst: Rect,
+    },
+    CopySurface {
+        surface_size: (u32, u32),
+        src: Option<Rect>,
+        dst: Rect,
+    },
+    /// text is recorded as is, rather than as the surface it rasterizes to
+    Text {
+        text: String,
+        color: Color,
+        position: (i32, i32),
+    },
+    SetClipRect(Option<Rect>),
+    SetViewport(Option<Rect>),
+}
+
+/// doesn't draw anything; it keeps a list of the calls made to it. compare
+/// the list against an expected one to test what was drawn without depending
+/// on how sdl rasterizes it
+pub struct RecordingRenderer {
+    size: (u32, u32),
+    draw_color: Color,
+    clip_rect: Option<Rect>,
+    viewport: Option<Rect>,
+    transform: Transform,
+    calls: Vec<DrawCall>,
+}
+
+impl RecordingRenderer {
+    /// size is reported as the output size
+    pub fn new(size: (u32, u32)) -> Self {
+        Self {
+            size,
+            draw_color: Color::BLACK,
+            clip_rect: None,
+            viewport: None,
+            transform: Transform::identity(size),
+            calls: Vec::new(),
+        }
+    }
+
+    pub fn calls(&self) -> &[DrawCall] {
+        &self.calls
+    }
+
+    /// the calls so far, leaving none
+    pub fn take_calls(&mut self) -> Vec<DrawCall> {
+        std::mem::take(&mut self.calls)
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    fn output_size(&self) -> Result<(u32, u32), String> {
+        Ok(self.size)
+    }
+
+    fn set_draw_color(&mut self, color: Color) {
+        self.draw_color = color;
+    }
+
+    fn draw_color(&self) -> Color {
+        self.draw_color
+    }
+
+    fn clear(&mut self) {
+        self.calls.push(DrawCall::Clear(self.draw_color));
+    }
+
+    fn fill_rect(&mut self, rect: Rect) -> Result<(), String> {
+        self.calls.push(DrawCall::FillRect(rect, self.draw_color));
+        Ok(())
+    }
+
+    fn draw_rect(&mut self, rect: Rect) -> Result<(), String> {
+        self.calls.push(DrawCall::DrawRect(rect, self.draw_color));
+        Ok(())
+    }
+
+    fn draw_line(&mut self, start: Point, end: Point) -> Result<(), String> {
+        self.calls
+            .push(DrawCall::DrawLine(start, end, self.draw_color));
+        Ok(())
+    }
+
+    fn copy(&mut self, texture: &Texture, src: Option<Rect>, dst: Rect) -> Result<(), String> {
+        let q = texture.query();
+        self.calls.push(DrawCall::Copy {
+            texture_size: (q.width, q.height),
+            src,
+            dst,
+        });
+        Ok(())
+    }
+
+    fn copy_surface(
+        &mut self,
+        surface: &SurfaceRef,
+        src: Option<Rect>,
+        dst: Rect,
+    ) -> Result<(), String> {
+        self.calls.push(DrawCall::CopySurface {
+            surface_size: surface.size(),
+            src,
+            dst,
+        });
+        Ok(())
+    }
+
+    fn draw_text(
+        &mut self,
+        font: &Font,
+        text: &str,
+        color: Color,
+        position: (i32, i32),
+    ) -> Result<Rect, String> {
+        let (w, h) = font.size_of(text).map_err(|e| e.to_string())?;
+        self.calls.push(DrawCall::Text {
+            text: text.to_owned(),
+            color,
+            position,
+        });
+        Ok(Rect::new(position.0, position.1, w, h))
+    }
+
+    fn set_clip_rect(&mut self, rect: Option<Rect>) {
+        self.clip_rect = rect;
+        self.calls.push(DrawCall::SetClipRect(rect));
+    }
+
+    fn clip_rect(&self) -> Option<Rect> {
+        self.clip_rect
+    }
+
+    fn set_viewport(&mut self, rect: Option<Rect>) {
+        self.viewport = rect;
+        self.calls.push(DrawCall::SetViewport(rect));
+    }
+
+    fn viewport(&self) -> Rect {
+        self.viewport
+            .unwrap_or_else(|| Rect::new(0, 0, self.size.0.max(1), self.size.1.max(1)))
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    fn transform(&self) -> Transform {
+        self.transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(size: (u32, u32), color: Color) -> CachedSurface {
+        let mut surface = Surface::new(size.0, size.1, PixelFormatEnum::RGBA32).unwrap();
+        surface.fill_rect(None, color).unwrap();
+        CachedSurface::new(surface)
+    }
+
+    fn cached_renderer(capacity: usize) -> SurfaceRenderer {
+        let surface = Surface::new(4, 1, PixelFormatEnum::RGBA32).unwrap();
+        CanvasRenderer::new(Canvas::from_surface(surface).unwrap()).with_texture_cache(capacity)
+    }
+
+    fn pixel(renderer: &SurfaceRenderer, x: usize) -> [u8; 4] {
+        let surface = renderer.surface();
+        surface.with_lock(|bytes| bytes[x * 4..x * 4 + 4].try_into().unwrap())
+    }
+
+    fn cached(renderer: &SurfaceRenderer) -> Vec<u64> {
+        let textures = &renderer.textures.as_ref().unwrap().textures;
+        textures.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn cached_surface_is_uploaded_once() {
+        let mut renderer = cached_renderer(4);
+        let red = filled((1, 1), Color::RED);
+        renderer
+            .copy_cached(&red, None, Rect::new(0, 0, 1, 1))
+            .unwrap();
+        renderer
+            .copy_cached(&red, None, Rect::new(2, 0, 1, 1))
+            .unwrap();
+        assert_eq!(cached(&renderer), [red.id]);
+        assert_eq!(pixel(&renderer, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&renderer, 1), [0, 0, 0, 0]);
+        assert_eq!(pixel(&renderer, 2), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn least_recently_drawn_texture_is_dropped() {
+        let mut renderer = cached_renderer(2);
+        let surfaces = [
+            filled((1, 1), Color::RED),
+            filled((1, 1), Color::GREEN),
+            filled((1, 1), Color::BLUE),
+        ];
+        // red is drawn again before blue, so green is the least recent
+        for (x, i) in [0, 1, 2, 3].into_iter().zip([0, 1, 0, 2]) {
+            renderer
+                .copy_cached(&surfaces[i], None, Rect::new(x, 0, 1, 1))
+                .unwrap();
+        }
+        assert_eq!(cached(&renderer), [surfaces[2].id, surfaces[0].id]);
+        assert_eq!(pixel(&renderer, 1), [0, 255, 0, 255]);
+        assert_eq!(pixel(&renderer, 3), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn without_a_cache_cached_surfaces_are_drawn_directly() {
+        let mut renderer = SurfaceRenderer::new_offscreen((2, 1)).unwrap();
+        let red = filled((2, 1), Color::RED);
+        renderer
+            .copy_cached(&red, Some(Rect::new(1, 0, 1, 1)), Rect::new(1, 0, 1, 1))
+            .unwrap();
+        assert!(renderer.textures.is_none());
+        assert_eq!(pixel(&renderer, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(&renderer, 1), [255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,466 @@
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::{Keycode, Mod, Scancode},
+    mouse::{MouseButton, MouseState, MouseWheelDirection},
+};
+
+use crate::core::LayerSettings;
+
+extern crate sdl2;
+
+/// an sdl event in a form that can be saved to a replay file.\
+/// only events which typically drive the game are kept. timestamps are dropped
+/// since replays are aligned by tick instead, and the window id is replaced
+/// with that of the replaying window
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub enum RecordedEvent {
+    Quit,
+    Window(RecordedWindowEvent),
+    KeyDown {
+        keycode: Option<i32>,
+        scancode: Option<i32>,
+        keymod: u16,
+        repeat: bool,
+    },
+    KeyUp {
+        keycode: Option<i32>,
+        scancode: Option<i32>,
+        keymod: u16,
+        repeat: bool,
+    },
+    TextInput {
+        text: String,
+    },
+    MouseMotion {
+        mousestate: u32,
+        x: i32,
+        y: i32,
+        xrel: i32,
+        yrel: i32,
+    },
+    MouseButtonDown {
+        mouse_btn: u8,
+        clicks: u8,
+        x: i32,
+        y: i32,
+    },
+    MouseButtonUp {
+        mouse_btn: u8,
+        clicks: u8,
+        x: i32,
+        y: i32,
+    },
+    MouseWheel {
+        x: i32,
+        y: i32,
+        direction: u32,
+        precise_x: f32,
+        precise_y: f32,
+    },
+}
+
+/// corresponds to variants of `sdl2::event::WindowEvent`
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub enum RecordedWindowEvent {
+    None,
+    Shown,
+    Hidden,
+    Exposed,
+    Moved(i32, i32),
+    Resized(i32, i32),
+    SizeChanged(i32, i32),
+    Minimized,
+    Maximized,
+    Restored,
+    Enter,
+    Leave,
+    FocusGained,
+    FocusLost,
+    Close,
+    TakeFocus,
+    HitTest,
+    ICCProfChanged,
+    DisplayChanged(i32),
+}
+
+impl RecordedWindowEvent {
+    fn new(e: &WindowEvent) -> Self {
+        match *e {
+            WindowEvent::None => Self::None,
+            WindowEvent::Shown => Self::Shown,
+            WindowEvent::Hidden => Self::Hidden,
+            WindowEvent::Exposed => Self::Exposed,
+            WindowEvent::Moved(x, y) => Self::Moved(x, y),
+            WindowEvent::Resized(x, y) => Self::Resized(x, y),
+            WindowEvent::SizeChanged(x, y) => Self::SizeChanged(x, y),
+            WindowEvent::Minimized => Self::Minimized,
+            WindowEvent::Maximized => Self::Maximized,
+            WindowEvent::Restored => Self::Restored,
+            WindowEvent::Enter => Self::Enter,
+            WindowEvent::Leave => Self::Leave,
+            WindowEvent::FocusGained => Self::FocusGained,
+            WindowEvent::FocusLost => Self::FocusLost,
+            WindowEvent::Close => Self::Close,
+            WindowEvent::TakeFocus => Self::TakeFocus,
+            WindowEvent::HitTest => Self::HitTest,
+            WindowEvent::ICCProfChanged => Self::ICCProfChanged,
+            WindowEvent::DisplayChanged(d) => Self::DisplayChanged(d),
+        }
+    }
+
+    fn to_event(&self) -> WindowEvent {
+        match *self {
+            Self::None => WindowEvent::None,
+            Self::Shown => WindowEvent::Shown,
+            Self::Hidden => WindowEvent::Hidden,
+            Self::Exposed => WindowEvent::Exposed,
+            Self::Moved(x, y) => WindowEvent::Moved(x, y),
+            Self::Resized(x, y) => WindowEvent::Resized(x, y),
+            Self::SizeChanged(x, y) => WindowEvent::SizeChanged(x, y),
+            Self::Minimized => WindowEvent::Minimized,
+            Self::Maximized => WindowEvent::Maximized,
+            Self::Restored => WindowEvent::Restored,
+            Self::Enter => WindowEvent::Enter,
+            Self::Leave => WindowEvent::Leave,
+            Self::FocusGained => WindowEvent::FocusGained,
+            Self::FocusLost => WindowEvent::FocusLost,
+            Self::Close => WindowEvent::Close,
+            Self::TakeFocus => WindowEvent::TakeFocus,
+            Self::HitTest => WindowEvent::HitTest,
+            Self::ICCProfChanged => WindowEvent::ICCProfChanged,
+            Self::DisplayChanged(d) => WindowEvent::DisplayChanged(d),
+        }
+    }
+}
+
+impl RecordedEvent {
+    /// None if this type of event isn't recorded
+    pub fn new(e: &Event) -> Option<Self> {
+        Some(match e {
+            Event::Quit { .. } => Self::Quit,
+            Event::Window { win_event, .. } => Self::Window(RecordedWindowEvent::new(win_event)),
+            Event::KeyDown {
+                keycode,
+                scancode,
+                keymod,
+                repeat,
+                ..
+            } => Self::KeyDown {
+                keycode: keycode.map(|k| k as i32),
+                scancode: scancode.map(|s| s as i32),
+                keymod: keymod.bits(),
+                repeat: *repeat,
+            },
+            Event::KeyUp {
+                keycode,
+                scancode,
+                keymod,
+                repeat,
+                ..
+            } => Self::KeyUp {
+                keycode: keycode.map(|k| k as i32),
+                scancode: scancode.map(|s| s as i32),
+                keymod: keymod.bits(),
+                repeat: *repeat,
+            },
+            Event::TextInput { text, .. } => Self::TextInput { text: text.clone() },
+            Event::MouseMotion {
+                mousestate,
+                x,
+                y,
+                xrel,
+                yrel,
+                ..
+            } => Self::MouseMotion {
+                mousestate: mousestate.to_sdl_state(),
+                x: *x,
+                y: *y,
+                xrel: *xrel,
+                yrel: *yrel,
+            },
+            Event::MouseButtonDown {
+                mouse_btn,
+                clicks,
+                x,
+                y,
+                ..
+            } => Self::MouseButtonDown {
+                mouse_btn: *mouse_btn as u8,
+                clicks: *clicks,
+                x: *x,
+                y: *y,
+            },
+            Event::MouseButtonUp {
+                mouse_btn,
+                clicks,
+                x,
+                y,
+                ..
+            } => Self::MouseButtonUp {
+                mouse_btn: *mouse_btn as u8,
+                clicks: *clicks,
+                x: *x,
+                y: *y,
+            },
+            Event::MouseWheel {
+                x,
+                y,
+                direction,
+                precise_x,
+                precise_y,
+                ..
+            } => Self::MouseWheel {
+                x: *x,
+                y: *y,
+                direction: direction.to_ll(),
+                precise_x: *precise_x,
+                precise_y: *precise_y,
+            },
+            _ => return None,
+        })
+    }
+
+    /// recreate the sdl event, as if it happened in the window with this id
+    pub fn to_event(&self, window_id: u32) -> Event {
+        match self {
+            Self::Quit => Event::Quit { timestamp: 0 },
+            Self::Window(win_event) => Event::Window {
+                timestamp: 0,
+                window_id,
+                win_event: win_event.to_event(),
+            },
+            Self::KeyDown {
+                keycode,
+                scancode,
+                keymod,
+                repeat,
+            } => Event::KeyDown {
+                timestamp: 0,
+                window_id,
+                keycode: keycode.and_then(Keycode::from_i32),
+                scancode: scancode.and_then(Scancode::from_i32),
+                keymod: Mod::from_bits_truncate(*keymod),
+                repeat: *repeat,
+            },
+            Self::KeyUp {
+                keycode,
+                scancode,
+                keymod,
+                repeat,
+            } => Event::KeyUp {
+                timestamp: 0,
+                window_id,
+                keycode: keycode.and_then(Keycode::from_i32),
+                scancode: scancode.and_then(Scancode::from_i32),
+                keymod: Mod::from_bits_truncate(*keymod),
+                repeat: *repeat,
+            },
+            Self::TextInput { text } => Event::TextInput {
+                timestamp: 0,
+                window_id,
+                text: text.clone(),
+            },
+            Self::MouseMotion {
+                mousestate,
+                x,
+                y,
+                xrel,
+                yrel,
+            } => Event::MouseMotion {
+                timestamp: 0,
+                window_id,
+                which: 0,
+                mousestate: MouseState::from_sdl_state(*mousestate),
+                x: *x,
+                y: *y,
+                xrel: *xrel,
+                yrel: *yrel,
+            },
+            Self::MouseButtonDown {
+                mouse_btn,
+                clicks,
+                x,
+                y,
+            } => Event::MouseButtonDown {
+                timestamp: 0,
+                window_id,
+                which: 0,
+                mouse_btn: MouseButton::from_ll(*mouse_btn),
+                clicks: *clicks,
+                x: *x,
+                y: *y,
+            },
+            Self::MouseButtonUp {
+                mouse_btn,
+                clicks,
+                x,
+                y,
+            } => Event::MouseButtonUp {
+                timestamp: 0,
+                window_id,
+                which: 0,
+                mouse_btn: MouseButton::from_ll(*mouse_btn),
+                clicks: *clicks,
+                x: *x,
+                y: *y,
+            },
+            Self::MouseWheel {
+                x,
+                y,
+                direction,
+                precise_x,
+                precise_y,
+            } => Event::MouseWheel {
+                timestamp: 0,
+                window_id,
+                which: 0,
+                x: *x,
+                y: *y,
+                direction: MouseWheelDirection::from_ll(*direction),
+                precise_x: *precise_x,
+                precise_y: *precise_y,
+            },
+        }
+    }
+}
+
+/// the parts of the game state which change how it's stepped but aren't
+/// saved, as they were when recording started
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct ReplaySettings {
+    pub(crate) paused: bool,
+    pub(crate) pending_advance: u64,
+    pub(crate) time_scale: f32,
+    pub(crate) tick_duration: std::time::Duration,
+    pub(crate) gravity: (f32, f32),
+    /// every registered layer, in render order
+    pub(crate) layers: Vec<(String, LayerSettings)>,
+}
+
+/// the events given to the event handler in `GameState::run`, and the state of
+/// the game when recording started. see `GameState::start_recording`
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Replay {
+    /// the persistent entities when recording started, same as the contents
+    /// of a save file
+    pub(crate) start: serde_json::Value,
+    pub(crate) settings: ReplaySettings,
+    /// each event is tagged with the tick that it happened before. in order
+    pub(crate) events: Vec<(u64, RecordedEvent)>,
+}
+
+impl Replay {
+    pub(crate) fn new(start: serde_json::Value, settings: ReplaySettings) -> Self {
+        Self {
+            start,
+            settings,
+            events: Vec::new(),
+        }
+    }
+
+    /// the event is dropped if it isn't a recorded type
+    pub(crate) fn record(&mut self, tick: u64, e: &Event) {
+        if let Some(e) = RecordedEvent::new(e) {
+            self.events.push((tick, e));
+        }
+    }
+
+    /// number of recorded events
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// the tick of the last recorded event, if there is one
+    pub fn last_tick(&self) -> Option<u64> {
+        self.events.last().map(|(tick, _)| *tick)
+    }
+
+    /// overrides or creates a new replay file
+    pub fn save(&self, path: String) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+        let mut writer = std::io::BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub fn load(path: String) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let reader = std::io::BufReader::new(file);
+        serde_json::from_reader(reader).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ReplaySettings {
+        ReplaySettings {
+            paused: true,
+            pending_advance: 2,
+            time_scale: 0.3f32,
+            tick_duration: std::time::Duration::from_secs_f32(1f32 / 60f32),
+            gravity: (0f32, 9.81f32),
+            layers: vec![(
+                "world".to_string(),
+                LayerSettings {
+                    parallax: 0.7f32,
+                    ..Default::default()
+                },
+            )],
+        }
+    }
+
+    #[test]
+    fn save_and_load_keep_floats_exact() {
+        // f32s widened to f64, as they are in the start of a recording. some
+        // of these don't parse back the same without serde_json's
+        // float_roundtrip
+        let floats: Vec<f64> = [416.70977f32, 383.9339f32, 38.85897f32, 0.1f32]
+            .into_iter()
+            .map(f64::from)
+            .chain([0.1f64 + 0.2f64, 1e-300f64])
+            .collect();
+        let mut replay = Replay::new(serde_json::json!({ "floats": floats }), settings());
+        replay.events.push((
+            3,
+            RecordedEvent::MouseWheel {
+                x: 0,
+                y: 1,
+                direction: 0,
+                precise_x: 0.1f32,
+                precise_y: 416.70977f32,
+            },
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay").to_string_lossy().into_owned();
+        replay.save(path.clone()).unwrap();
+        let loaded = Replay::load(path).unwrap();
+
+        let loaded_floats: Vec<f64> =
+            serde_json::from_value(loaded.start["floats"].clone()).unwrap();
+        assert_eq!(
+            loaded_floats
+                .iter()
+                .map(|f| f.to_bits())
+                .collect::<Vec<_>>(),
+            floats.iter().map(|f| f.to_bits()).collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.settings, settings());
+        assert!(matches!(
+            loaded.events[..],
+            [(
+                3,
+                RecordedEvent::MouseWheel {
+                    precise_x: 0.1f32,
+                    precise_y: 416.70977f32,
+                    ..
+                }
+            )]
+        ));
+    }
+}
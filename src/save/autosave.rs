@@ -0,0 +1,291 @@
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use super::{format, write_atomic, SaveFormat, SaveMetadata, SaveSlots};
+
+// the entities are serialized on the main thread (they aren't Send), but
+// compressing and writing the file happen on a background thread so that the
+// frame isn't held up by them or the disk
+
+/// when `GameState::run` autosaves. see `GameState::enable_autosave`
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutosavePolicy {
+    /// every this much simulated time, counted in ticks of the tick duration
+    /// at the time. None to disable
+    pub interval: Option<Duration>,
+    /// when the window loses focus, e.g. the player switches away
+    pub on_focus_lost: bool,
+    /// when run returns, whether the event handler ended it or there was an
+    /// error. run waits for the save to be written
+    pub on_quit: bool,
+    /// autosaves go to slots autosave_1 up to this, overwriting the oldest
+    pub slots: usize,
+}
+
+impl Default for AutosavePolicy {
+    /// every minute, on focus loss and on quit, in three slots
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(60)),
+            on_focus_lost: true,
+            on_quit: true,
+            slots: 3,
+        }
+    }
+}
+
+struct Job {
+    slot: String,
+    /// serialized with the format's codec
+    body: Vec<u8>,
+    format: SaveFormat,
+    metadata: SaveMetadata,
+}
+
+pub(crate) struct Autosaver {
+    pub policy: AutosavePolicy,
+    slots: SaveSlots,
+    /// index of the slot written next, from 0
+    next_slot: usize,
+    /// tick of the last autosave
+    pub last_tick: u64,
+    /// jobs sent whose results haven't been received
+    pending: usize,
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(String, Result<(), String>)>,
+    thread: Option<JoinHandle<()>>,
+    on_error: Box<dyn FnMut(String)>,
+}
+
+impl Autosaver {
+    pub fn new(
+        slots: SaveSlots,
+        policy: AutosavePolicy,
+        tick: u64,
+        on_error: Box<dyn FnMut(String)>,
+    ) -> Result<Self, String> {
+        assert!(policy.slots > 0, "autosave needs at least one slot");
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let dir = slots.clone();
+        let thread = std::thread::Builder::new()
+            .name("autosave".to_owned())
+            .spawn(move || {
+                for job in job_receiver {
+                    let result = dir.path(&job.slot).and_then(|path| {
+                        let bytes = format::encode_body(&job.body, job.format, &job.metadata)?;
+                        // the other autosave slots serve as backups
+                        write_atomic(&path, &bytes, 0)
+                    });
+                    if result_sender.send((job.slot, result)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut autosaver = Self {
+            policy,
+            slots,
+            next_slot: 0,
+            last_tick: tick,
+            pending: 0,
+            jobs: Some(jobs),
+            results,
+            thread: Some(thread),
+            on_error,
+        };
+        autosaver.next_slot = autosaver.oldest_slot();
+        Ok(autosaver)
+    }
+
+    fn slot_name(index: usize) -> String {
+        format!("autosave_{}", index + 1)
+    }
+
+    // the first missing slot, otherwise the one saved longest ago
+    fn oldest_slot(&self) -> usize {
+        (0..self.policy.slots)
+            .min_by_key(|i| match self.slots.metadata(&Self::slot_name(*i)) {
+                Ok(Some(metadata)) => Some(metadata.timestamp),
+                _ => None,
+            })
+            .unwrap_or(0)
+    }
+
+    /// the interval has passed since the last autosave, at this tick duration
+    pub fn is_due(&self, tick: u64, tick_duration: Duration) -> bool {
+        self.policy.interval.is_some_and(|interval| {
+            let ticks = (interval.as_secs_f64() / tick_duration.as_secs_f64()).round();
+            tick - self.last_tick >= (ticks as u64).max(1)
+        })
+    }
+
+    /// a write hasn't finished
+    pub fn is_busy(&self) -> bool {
+        self.pending != 0
+    }
+
+    pub fn submit(&mut self, body: Vec<u8>, format: SaveFormat, metadata: SaveMetadata, tick: u64) {
+        // the policy's number of slots may have changed since
+        let index = self.next_slot % self.policy.slots.max(1);
+        self.next_slot = index + 1;
+        self.last_tick = tick;
+        let job = Job {
+            slot: Self::slot_name(index),
+            body,
+            format,
+            metadata,
+        };
+        let sent = match &self.jobs {
+            Some(jobs) => jobs.send(job).is_ok(),
+            None => false,
+        };
+        if sent {
+            self.pending += 1;
+        } else {
+            (self.on_error)("autosave thread has stopped".to_owned());
+        }
+    }
+
+    pub fn report_error(&mut self, e: String) {
+        (self.on_error)(e);
+    }
+
+    fn handle_result(&mut self, (slot, result): (String, Result<(), String>)) {
+        self.pending -= 1;
+        if let Err(e) = result {
+            (self.on_error)(format!("autosave to {}: {}", slot, e));
+        }
+    }
+
+    /// report the writes which have finished, without waiting
+    pub fn poll(&mut self) {
+        while let Ok(result) = self.results.try_recv() {
+            self.handle_result(result);
+        }
+    }
+
+    /// wait for every write to finish
+    pub fn flush(&mut self) {
+        while self.pending != 0 {
+            match self.results.recv() {
+                Ok(result) => self.handle_result(result),
+                Err(_) => {
+                    self.pending = 0;
+                    (self.on_error)("autosave thread has stopped".to_owned());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Autosaver {
+    // writes in progress are finished
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn new_autosaver(
+        dir: &std::path::Path,
+        policy: AutosavePolicy,
+    ) -> (Autosaver, Rc<RefCell<Vec<String>>>) {
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let on_error = {
+            let errors = errors.clone();
+            Box::new(move |e| errors.borrow_mut().push(e))
+        };
+        let slots = SaveSlots::in_dir(dir).unwrap();
+        (Autosaver::new(slots, policy, 0, on_error).unwrap(), errors)
+    }
+
+    fn submit(autosaver: &mut Autosaver, timestamp: u64, tick: u64) {
+        let body = format::serialize(&tick, SaveFormat::COMPACT.codec).unwrap();
+        let metadata = SaveMetadata {
+            timestamp,
+            ..Default::default()
+        };
+        autosaver.submit(body, SaveFormat::COMPACT, metadata, tick);
+    }
+
+    #[test]
+    fn interval_is_counted_in_ticks_of_the_tick_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = AutosavePolicy {
+            interval: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let (mut autosaver, _) = new_autosaver(dir.path(), policy);
+        let tenth = Duration::from_secs_f32(0.1);
+        assert!(!autosaver.is_due(9, tenth));
+        assert!(autosaver.is_due(10, tenth));
+        assert!(!autosaver.is_due(59, Duration::from_secs_f32(1f32 / 60f32)));
+        assert!(autosaver.is_due(60, Duration::from_secs_f32(1f32 / 60f32)));
+        // at least a tick, even if the tick is longer than the interval
+        assert!(!autosaver.is_due(0, Duration::from_secs(5)));
+        assert!(autosaver.is_due(1, Duration::from_secs(5)));
+
+        autosaver.last_tick = 10;
+        assert!(!autosaver.is_due(19, tenth));
+        assert!(autosaver.is_due(20, tenth));
+
+        autosaver.policy.interval = None;
+        assert!(!autosaver.is_due(1000, tenth));
+    }
+
+    #[test]
+    fn flush_waits_for_every_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut autosaver, errors) = new_autosaver(dir.path(), AutosavePolicy::default());
+        for tick in 1..=4 {
+            submit(&mut autosaver, tick, tick);
+        }
+        assert_eq!(autosaver.last_tick, 4);
+        autosaver.flush();
+        assert!(!autosaver.is_busy());
+        assert!(errors.borrow().is_empty());
+
+        // into the three slots in turn, so the fourth replaced the first
+        let slots = SaveSlots::in_dir(dir.path()).unwrap();
+        let timestamp = |slot| slots.metadata(slot).unwrap().unwrap().timestamp;
+        assert_eq!(timestamp("autosave_1"), 4);
+        assert_eq!(timestamp("autosave_2"), 2);
+        assert_eq!(timestamp("autosave_3"), 3);
+
+        // a new autosaver continues from the oldest slot
+        drop(autosaver);
+        let (mut autosaver, _) = new_autosaver(dir.path(), AutosavePolicy::default());
+        submit(&mut autosaver, 5, 5);
+        autosaver.flush();
+        assert_eq!(timestamp("autosave_2"), 5);
+    }
+
+    #[test]
+    fn failed_writes_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut autosaver, errors) = new_autosaver(dir.path(), AutosavePolicy::default());
+        // a directory can't be replaced by a file
+        std::fs::create_dir(dir.path().join("autosave_1.sav")).unwrap();
+        submit(&mut autosaver, 1, 1);
+        autosaver.flush();
+        let errors = errors.borrow();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("autosave to autosave_1"),
+            "{}",
+            errors[0]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::de::IgnoredAny;
+
+use super::format;
+
+// a save is written to a temp file beside the target, synced to disk, then
+// renamed over the target. a crash or full disk partway through leaves the
+// previous save in place. before the rename, the previous save becomes the
+// first backup (save.bak1), and older backups move down one (save.bak2, ...).
+// a previous save which is corrupt is replaced without becoming a backup
+
+/// the nth most recent backup of a save, from 1
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(format!(".bak{}", n));
+    PathBuf::from(s)
+}
+
+// unique to each write, so that writers of the same path (e.g. two instances
+// of the game) don't write into each other's temp file
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut s = path.as_os_str().to_owned();
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    s.push(format!(".{}.{}.tmp", std::process::id(), n));
+    PathBuf::from(s)
+}
+
+/// replace the file at path with bytes, keeping this many of the previous
+/// versions as backups
+pub fn write_atomic(path: &Path, bytes: &[u8], backups: usize) -> Result<(), String> {
+    let temp = temp_path(path);
+    let written = fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(format!("{}: {}", temp.display(), e));
+    }
+    let replaced = if backups > 0 && is_valid(path) {
+        rotate_backups(path, backups)
+    } else {
+        Ok(())
+    }
+    .and_then(|_| fs::rename(&temp, path).map_err(|e| format!("{}: {}", path.display(), e)));
+    if replaced.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    replaced?;
+    sync_parent(path)
+}
+
+// whether the file at path is a save which decodes, so that a corrupt one
+// doesn't push a good backup out
+fn is_valid(path: &Path) -> bool {
+    fs::read(path).is_ok_and(|bytes| {
+        format::unpack(&bytes)
+            .and_then(|(codec, body)| format::deserialize::<IgnoredAny>(&body, codec))
+            .is_ok()
+    })
+}
+
+// the oldest is overwritten. the save itself is linked (or copied) rather
+// than moved so that there's always a file at path
+fn rotate_backups(path: &Path, backups: usize) -> Result<(), String> {
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))
+                .map_err(|e| format!("{}: {}", from.display(), e))?;
+        }
+    }
+    let first = backup_path(path, 1);
+    let _ = fs::remove_file(&first);
+    fs::hard_link(path, &first)
+        .or_else(|_| fs::copy(path, &first).map(|_| ()))
+        .map_err(|e| format!("{}: {}", first.display(), e))
+}
+
+// so that the rename itself is on disk. directories can't be opened for this
+// on windows, where it isn't needed
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<(), String> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// read the save at path with read. if it's missing or read fails, the newest
+/// of this many backups which reads is used instead. returns the path that
+/// was read
+pub fn read_with_fallback<T, F>(
+    path: &Path,
+    backups: usize,
+    mut read: F,
+) -> Result<(T, PathBuf), String>
+where
+    F: FnMut(&[u8]) -> Result<T, String>,
+{
+    let mut errors = Vec::new();
+    let backups = (1..=backups)
+        .map(|n| backup_path(path, n))
+        .filter(|backup| backup.exists());
+    for candidate in std::iter::once(path.to_owned()).chain(backups) {
+        let decoded = fs::read(&candidate)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| read(&bytes));
+        match decoded {
+            Ok(save) => return Ok((save, candidate)),
+            Err(e) => errors.push(format!("{}: {}", candidate.display(), e)),
+        }
+    }
+    Err(errors.join(". "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::format;
+
+    fn save(n: u64) -> Vec<u8> {
+        format::encode(&n, format::SaveFormat::COMPACT, &Default::default()).unwrap()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<u64, String> {
+        let (codec, body) = format::unpack(bytes)?;
+        format::deserialize(&body, codec)
+    }
+
+    fn read(path: &Path) -> u64 {
+        decode(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        for n in 1..=4 {
+            write_atomic(&path, &save(n), 2).unwrap();
+        }
+        assert_eq!(read(&path), 4);
+        assert_eq!(read(&backup_path(&path, 1)), 3);
+        assert_eq!(read(&backup_path(&path, 2)), 2);
+        assert!(!backup_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn corrupt_save_is_not_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        write_atomic(&path, &save(1), 2).unwrap();
+        write_atomic(&path, &save(2), 2).unwrap();
+        fs::write(&path, b"GSAV corrupt").unwrap();
+        write_atomic(&path, &save(3), 2).unwrap();
+        assert_eq!(read(&path), 3);
+        assert_eq!(read(&backup_path(&path, 1)), 1);
+        assert!(!backup_path(&path, 2).exists());
+
+        let mut truncated = save(4);
+        truncated.pop();
+        fs::write(&path, truncated).unwrap();
+        write_atomic(&path, &save(5), 2).unwrap();
+        assert_eq!(read(&backup_path(&path, 1)), 1);
+    }
+
+    #[test]
+    fn concurrent_writers_each_write_whole_saves() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        std::thread::scope(|scope| {
+            for writer in 0..4 {
+                let path = &path;
+                scope.spawn(move || {
+                    for n in 0..20 {
+                        write_atomic(path, &save(writer * 100 + n), 0).unwrap();
+                    }
+                });
+            }
+        });
+        assert!(read(&path) % 100 == 19);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn no_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        write_atomic(&path, &save(1), 0).unwrap();
+        write_atomic(&path, &save(2), 0).unwrap();
+        assert_eq!(read(&path), 2);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn fallback_is_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        for n in 1..=3 {
+            write_atomic(&path, &save(n), 2).unwrap();
+        }
+        let (value, read) = read_with_fallback(&path, 2, decode).unwrap();
+        assert_eq!((value, read), (3, path.clone()));
+
+        fs::write(&path, b"GSAV corrupt").unwrap();
+        let (value, read) = read_with_fallback(&path, 2, decode).unwrap();
+        assert_eq!((value, read), (2, backup_path(&path, 1)));
+
+        fs::remove_file(backup_path(&path, 1)).unwrap();
+        let (value, read) = read_with_fallback(&path, 2, decode).unwrap();
+        assert_eq!((value, read), (1, backup_path(&path, 2)));
+
+        // backups beyond the number given aren't used
+        assert!(read_with_fallback(&path, 1, decode).is_err());
+    }
+
+    #[test]
+    fn fallback_when_read_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        for n in 1..=3 {
+            write_atomic(&path, &save(n), 2).unwrap();
+        }
+        // e.g. a save which decodes, but fails to migrate or deserialize
+        let reject_3 = |bytes: &[u8]| match decode(bytes)? {
+            3 => Err("can't migrate".to_owned()),
+            n => Ok(n),
+        };
+        let (value, read) = read_with_fallback(&path, 2, reject_3).unwrap();
+        assert_eq!((value, read), (2, backup_path(&path, 1)));
+    }
+
+    #[test]
+    fn fallback_errors_name_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save");
+        write_atomic(&path, &save(1), 1).unwrap();
+        write_atomic(&path, &save(2), 1).unwrap();
+        fs::write(&path, b"{").unwrap();
+        fs::write(backup_path(&path, 1), b"{").unwrap();
+        let e = read_with_fallback(&path, 1, decode).unwrap_err();
+        assert!(e.contains(&path.display().to_string()), "{}", e);
+        assert!(
+            e.contains(&backup_path(&path, 1).display().to_string()),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn failed_write_removes_the_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // a directory can't be replaced by a file
+        let path = dir.path().join("save");
+        fs::create_dir(&path).unwrap();
+        assert!(write_atomic(&path, &save(1), 0).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert!(path.is_dir());
+
+        let missing = dir.path().join("missing").join("save");
+        assert!(write_atomic(&missing, &save(1), 1).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,491 @@
+use std::{borrow::Cow, io::Read};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::SaveMetadata;
+
+// a save file starts with a container header:
+//
+//     magic: "GSAV"
+//     version: u8          of the container
+//     codec: u8            see SaveCodec
+//     flags: u8            COMPRESSED
+//     length: u64 le       of the body before compression (lz4 block)
+//     checksum: u32 le     crc32 of everything after the header
+//
+// then the slot metadata, so that it can be read without the rest of the
+// file:
+//
+//     length: u32 le, json of SaveMetadata
+//     length: u32 le, thumbnail png (empty for none)
+//
+// and lastly the body.\
+// files without the magic are json from before the container, and load as is
+
+const MAGIC: &[u8; 4] = b"GSAV";
+const CONTAINER_VERSION: u8 = 1;
+const HEADER_LENGTH: usize = 19;
+
+const COMPRESSED: u8 = 1;
+
+/// how the save is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveCodec {
+    /// readable, for debugging
+    Json = 0,
+    /// cbor, which is smaller than json
+    Binary = 1,
+}
+
+impl SaveCodec {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::Json),
+            1 => Some(Self::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// how save files are written. loading detects the format on its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveFormat {
+    pub codec: SaveCodec,
+    /// with lz4, which favours speed over size
+    pub compressed: bool,
+}
+
+impl SaveFormat {
+    /// uncompressed, so that it can be read in a text editor
+    pub const JSON: Self = Self {
+        codec: SaveCodec::Json,
+        compressed: false,
+    };
+
+    /// compressed binary
+    pub const COMPACT: Self = Self {
+        codec: SaveCodec::Binary,
+        compressed: true,
+    };
+}
+
+impl Default for SaveFormat {
+    fn default() -> Self {
+        Self::COMPACT
+    }
+}
+
+/// the body of a save file, before compression
+pub(crate) fn serialize<T: serde::Serialize>(
+    save: &T,
+    codec: SaveCodec,
+) -> Result<Vec<u8>, String> {
+    match codec {
+        SaveCodec::Json => serde_json::to_vec(save).map_err(|e| e.to_string()),
+        SaveCodec::Binary => {
+            let mut body = Vec::new();
+            ciborium::into_writer(save, &mut body).map_err(|e| e.to_string())?;
+            Ok(body)
+        }
+    }
+}
+
+/// a save as the bytes of a save file
+pub(crate) fn encode<T: serde::Serialize>(
+    save: &T,
+    format: SaveFormat,
+    metadata: &SaveMetadata,
+) -> Result<Vec<u8>, String> {
+    encode_body(&serialize(save, format.codec)?, format, metadata)
+}
+
+/// the bytes of a save file from its body, as given by serialize with the
+/// format's codec
+pub(crate) fn encode_body(
+    body: &[u8],
+    format: SaveFormat,
+    metadata: &SaveMetadata,
+) -> Result<Vec<u8>, String> {
+    let length = body.len() as u64;
+
+    let mut checked = Vec::new();
+    let metadata_json = serde_json::to_vec(metadata).map_err(|e| e.to_string())?;
+    let thumbnail = metadata.thumbnail.as_deref().unwrap_or_default();
+    checked.extend_from_slice(&(metadata_json.len() as u32).to_le_bytes());
+    checked.extend_from_slice(&metadata_json);
+    checked.extend_from_slice(&(thumbnail.len() as u32).to_le_bytes());
+    checked.extend_from_slice(thumbnail);
+    if format.compressed {
+        checked.extend_from_slice(&lz4_flex::block::compress(body));
+    } else {
+        checked.extend_from_slice(body);
+    }
+
+    let mut out = Vec::with_capacity(HEADER_LENGTH + checked.len());
+    out.extend_from_slice(MAGIC);
+    out.push(CONTAINER_VERSION);
+    out.push(format.codec as u8);
+    let compressed = if format.compressed { COMPRESSED } else { 0 };
+    out.push(compressed);
+    out.extend_from_slice(&length.to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+    out.extend_from_slice(&checked);
+    Ok(out)
+}
+
+// a length prefixed section at the start of bytes, and what follows it
+fn split_section(bytes: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let length = bytes
+        .get(..4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or("save file metadata is truncated")?;
+    let rest = &bytes[4..];
+    if rest.len() < length {
+        return Err("save file metadata is truncated".to_owned());
+    }
+    Ok(rest.split_at(length))
+}
+
+struct Header {
+    codec: SaveCodec,
+    flags: u8,
+    length: usize,
+    checksum: u32,
+}
+
+// the header at the start of bytes, which start with the magic
+fn read_header(bytes: &[u8]) -> Result<Header, String> {
+    let header = bytes
+        .get(..HEADER_LENGTH)
+        .ok_or("save file header is truncated")?;
+    let version = header[4];
+    if version > CONTAINER_VERSION {
+        return Err(format!(
+            "save container version {} is newer than supported ({})",
+            version, CONTAINER_VERSION
+        ));
+    }
+    if version != CONTAINER_VERSION {
+        return Err(format!("save container version {} is unknown", version));
+    }
+    Ok(Header {
+        codec: SaveCodec::from_u8(header[5])
+            .ok_or(format!("save file has an unknown codec: {}", header[5]))?,
+        flags: header[6],
+        length: u64::from_le_bytes(header[7..15].try_into().unwrap()) as usize,
+        checksum: u32::from_le_bytes(header[15..19].try_into().unwrap()),
+    })
+}
+
+/// the codec and body of a save file in any format, for deserialize. errors
+/// if the checksum doesn't match
+pub(crate) fn unpack(bytes: &[u8]) -> Result<(SaveCodec, Cow<'_, [u8]>), String> {
+    if !bytes.starts_with(MAGIC) {
+        return Ok((SaveCodec::Json, Cow::Borrowed(bytes)));
+    }
+    let Header {
+        codec,
+        flags,
+        length,
+        checksum,
+    } = read_header(bytes)?;
+    let checked = &bytes[HEADER_LENGTH..];
+    if crc32fast::hash(checked) != checksum {
+        return Err("save file is corrupt (checksum mismatch)".to_owned());
+    }
+    let (_metadata, rest) = split_section(checked)?;
+    let (_thumbnail, stored) = split_section(rest)?;
+    let body = if flags & COMPRESSED != 0 {
+        Cow::Owned(decompress(stored, length)?)
+    } else {
+        Cow::Borrowed(stored)
+    };
+    if body.len() != length {
+        return Err("save file is corrupt (length mismatch)".to_owned());
+    }
+    Ok((codec, body))
+}
+
+/// the body of a save file, as given by unpack, deserialized as is
+pub(crate) fn deserialize<T: DeserializeOwned>(body: &[u8], codec: SaveCodec) -> Result<T, String> {
+    match codec {
+        SaveCodec::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+        SaveCodec::Binary => ciborium::from_reader(body).map_err(|e| e.to_string()),
+    }
+}
+
+/// the body of a save file as a json value, which can be migrated
+pub(crate) fn deserialize_value(body: &[u8], codec: SaveCodec) -> Result<Value, String> {
+    match codec {
+        SaveCodec::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+        SaveCodec::Binary => {
+            // through cbor's own value, since unlike json it allows map keys
+            // which aren't strings (e.g. a HashMap<u32, _> field)
+            let value: ciborium::Value =
+                ciborium::from_reader(body).map_err(|e| format!("save file is corrupt ({})", e))?;
+            serde_json::to_value(value).map_err(|e| e.to_string())
+        }
+    }
+}
+
+// an lz4 block expands to at most about 255 times its size. a length beyond
+// that is corrupt, and is rejected before it's allocated
+fn decompress(stored: &[u8], length: usize) -> Result<Vec<u8>, String> {
+    if length > stored.len().saturating_mul(255) {
+        return Err("save file is corrupt (length mismatch)".to_owned());
+    }
+    lz4_flex::block::decompress(stored, length).map_err(|e| format!("save file is corrupt ({})", e))
+}
+
+fn read_section(reader: &mut impl Read) -> Result<Vec<u8>, String> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length).map_err(|e| e.to_string())?;
+    let mut section = Vec::new();
+    reader
+        .by_ref()
+        .take(u32::from_le_bytes(length) as u64)
+        .read_to_end(&mut section)
+        .map_err(|e| e.to_string())?;
+    if section.len() != u32::from_le_bytes(length) as usize {
+        return Err("save file metadata is truncated".to_owned());
+    }
+    Ok(section)
+}
+
+/// only the metadata at the start of a save file, without reading the rest
+/// or verifying the checksum. None for json saves from before the container
+pub(crate) fn read_metadata(reader: &mut impl Read) -> Result<Option<SaveMetadata>, String> {
+    let mut header = Vec::with_capacity(HEADER_LENGTH);
+    reader
+        .by_ref()
+        .take(HEADER_LENGTH as u64)
+        .read_to_end(&mut header)
+        .map_err(|e| e.to_string())?;
+    if !header.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    read_header(&header)?;
+    let mut metadata: SaveMetadata =
+        serde_json::from_slice(&read_section(reader)?).map_err(|e| e.to_string())?;
+    let thumbnail = read_section(reader)?;
+    metadata.thumbnail = (!thumbnail.is_empty()).then_some(thumbnail);
+    Ok(Some(metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [SaveFormat; 4] = [
+        SaveFormat::JSON,
+        SaveFormat::COMPACT,
+        SaveFormat {
+            codec: SaveCodec::Json,
+            compressed: true,
+        },
+        SaveFormat {
+            codec: SaveCodec::Binary,
+            compressed: false,
+        },
+    ];
+
+    fn save() -> Value {
+        serde_json::json!({
+            "header": { "game_version": 2, "types": { "player": 1 } },
+            "state": {
+                "entities": [{ "type": "player", "x": 1.5, "name": "ab\u{e9}", "hp": -3 }],
+                "empty": [],
+                "none": null,
+                "big": u64::MAX,
+            },
+        })
+    }
+
+    fn metadata() -> SaveMetadata {
+        SaveMetadata {
+            timestamp: 1234,
+            summary: "level 2".to_owned(),
+            thumbnail: Some(vec![1, 2, 3]),
+            ..Default::default()
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Value, String> {
+        let (codec, body) = unpack(bytes)?;
+        deserialize_value(&body, codec)
+    }
+
+    // a valid checksum for whatever follows the header
+    fn fix_checksum(bytes: &mut [u8]) {
+        let checksum = crc32fast::hash(&bytes[HEADER_LENGTH..]);
+        bytes[15..19].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn roundtrip() {
+        for format in FORMATS {
+            let bytes = encode(&save(), format, &metadata()).unwrap();
+            assert_eq!(decode(&bytes).unwrap(), save(), "{:?}", format);
+            let read = read_metadata(&mut &bytes[..]).unwrap();
+            assert_eq!(read, Some(metadata()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn deserialize_without_a_value() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Save {
+            name: String,
+            x: f64,
+            big: u64,
+        }
+        let save = Save {
+            name: "ab\u{e9}".to_owned(),
+            x: 0.1,
+            big: u64::MAX,
+        };
+        for format in FORMATS {
+            let bytes = encode(&save, format, &metadata()).unwrap();
+            let (codec, body) = unpack(&bytes).unwrap();
+            assert_eq!(codec, format.codec);
+            assert_eq!(
+                deserialize::<Save>(&body, codec).unwrap(),
+                save,
+                "{:?}",
+                format
+            );
+        }
+        let bytes = serde_json::to_vec(&save).unwrap();
+        let (codec, body) = unpack(&bytes).unwrap();
+        assert_eq!(deserialize::<Save>(&body, codec).unwrap(), save);
+    }
+
+    #[test]
+    fn integer_map_keys_load_as_strings() {
+        let save = std::collections::HashMap::from([(7u32, "seven".to_owned())]);
+        for format in FORMATS {
+            let bytes = encode(&save, format, &SaveMetadata::default()).unwrap();
+            assert_eq!(decode(&bytes).unwrap(), serde_json::json!({ "7": "seven" }));
+            let (codec, body) = unpack(&bytes).unwrap();
+            assert_eq!(
+                deserialize::<std::collections::HashMap<u32, String>>(&body, codec).unwrap(),
+                save
+            );
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert!(decode(&[]).is_err());
+        for format in FORMATS {
+            let bytes = encode_body(&[], format, &SaveMetadata::default()).unwrap();
+            // there's no value in an empty body
+            assert!(decode(&bytes).is_err(), "{:?}", format);
+        }
+        let bytes = encode(&Value::Null, SaveFormat::COMPACT, &SaveMetadata::default()).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn incompressible() {
+        // xorshift, so the bytes have no repetition for lz4 to find
+        let mut x = 0x2545f491u32;
+        let noise: String = (0..4096)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                char::from(b'!' + (x % 90) as u8)
+            })
+            .collect();
+        let save = Value::String(noise);
+        for format in FORMATS {
+            let bytes = encode(&save, format, &SaveMetadata::default()).unwrap();
+            assert_eq!(decode(&bytes).unwrap(), save, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn truncated_is_an_error() {
+        for format in FORMATS {
+            let bytes = encode(&save(), format, &metadata()).unwrap();
+            for length in 0..bytes.len() {
+                assert!(decode(&bytes[..length]).is_err(), "{:?} {}", format, length);
+                let _ = read_metadata(&mut &bytes[..length]);
+            }
+        }
+    }
+
+    #[test]
+    fn read_metadata_of_truncated_file() {
+        let bytes = encode(&save(), SaveFormat::COMPACT, &metadata()).unwrap();
+        let json = serde_json::to_vec(&metadata()).unwrap();
+        // header, then the length prefixed json and thumbnail
+        let metadata_end = HEADER_LENGTH + 4 + json.len() + 4 + 3;
+        for length in 0..bytes.len() {
+            let read = read_metadata(&mut &bytes[..length]);
+            if length < MAGIC.len() {
+                // too short to be anything but json
+                assert_eq!(read, Ok(None), "{}", length);
+            } else if length < metadata_end {
+                assert!(read.is_err(), "{}", length);
+            } else {
+                // the body isn't read
+                assert_eq!(read, Ok(Some(metadata())), "{}", length);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_container_version_is_an_error() {
+        let mut bytes = encode(&save(), SaveFormat::COMPACT, &metadata()).unwrap();
+        for version in [0, CONTAINER_VERSION + 1] {
+            bytes[4] = version;
+            assert!(decode(&bytes).is_err());
+            assert!(read_metadata(&mut &bytes[..]).is_err());
+        }
+    }
+
+    #[test]
+    fn corrupt_is_an_error() {
+        for format in FORMATS {
+            let bytes = encode(&save(), format, &metadata()).unwrap();
+            for i in 4..bytes.len() {
+                let mut corrupt = bytes.clone();
+                corrupt[i] ^= 0x55;
+                // caught by the checksum
+                assert!(decode(&corrupt).is_err(), "{:?} {}", format, i);
+                // otherwise by decoding, without panicking
+                if i >= HEADER_LENGTH {
+                    fix_checksum(&mut corrupt);
+                    let _ = decode(&corrupt);
+                    let _ = read_metadata(&mut &corrupt[..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn impossible_length_is_an_error() {
+        for format in FORMATS {
+            let mut bytes = encode(&save(), format, &metadata()).unwrap();
+            // would be an out of memory abort if it were allocated
+            bytes[7..15].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+            assert!(decode(&bytes).is_err(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn checksum_is_crc32_ieee() {
+        // the standard check value, so that other tools can verify saves
+        assert_eq!(crc32fast::hash(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn legacy_json() {
+        let bytes = serde_json::to_vec(&save()).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), save());
+        assert_eq!(read_metadata(&mut &bytes[..]).unwrap(), None);
+    }
+}
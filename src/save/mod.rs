@@ -0,0 +1,10 @@
+pub mod schema;
+pub use self::schema::*;
+pub mod format;
+pub use self::format::*;
+pub mod file;
+pub use self::file::*;
+pub mod slots;
+pub use self::slots::*;
+pub mod autosave;
+pub use self::autosave::AutosavePolicy;
@@ -0,0 +1,485 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::format;
+
+// save files are an envelope of a header and the persistent state:
+//
+//     {"header": {"format_version": 1, ...}, "state": {"persistent_layers": ...}}
+//
+// when a persistent entity's fields change, bump its schema version and
+// register a migration which rewrites the old json into the new shape:
+//
+//     fn add_health(e: &mut serde_json::Value) -> Result<(), String> {
+//         e["health"] = 100.into();
+//         Ok(())
+//     }
+//     state.save_schema_mut().add_migration("Player", 0, add_health);
+//
+// on load, each entity is given the migrations from the version it was saved
+// with up to the current version, before it's deserialized. a save which needs
+// a step that has no migration fails to load
+
+/// version of the envelope itself. bumped when the engine changes the layout
+/// of save files
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+/// rewrites an entity saved with one schema version into the next. it's given
+/// the entity's json object, including the "type" tag
+pub type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// describes what wrote a save file
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SaveHeader {
+    /// saves from before versioning are format 0
+    pub format_version: u32,
+    /// crate version of the engine that wrote the save
+    pub engine_version: String,
+    /// see `SaveSchema::set_game_version`
+    pub game_version: u32,
+    /// schema version of each entity type with one registered. types not
+    /// listed are version 0
+    pub schema_versions: BTreeMap<String, u32>,
+}
+
+/// the game's version and the current schema version of each persistent
+/// entity type, along with migrations from older versions
+#[derive(Default)]
+pub struct SaveSchema {
+    game_version: u32,
+    /// type tag to current version
+    versions: HashMap<&'static str, u32>,
+    /// type tag to migrations keyed by the version they migrate from. None
+    /// for steps which deserialize as is (see set_schema_version)
+    migrations: HashMap<&'static str, BTreeMap<u32, Option<Migration>>>,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct SaveEnvelope<'a, T> {
+    pub header: SaveHeader,
+    pub state: &'a T,
+}
+
+#[derive(serde::Deserialize)]
+struct LoadedEnvelope<T> {
+    state: T,
+}
+
+// only the header of a save, skipping the state. None for saves from before
+// versioning
+#[derive(serde::Deserialize)]
+struct LoadedHeader {
+    header: Option<SaveHeader>,
+}
+
+impl SaveSchema {
+    pub fn game_version(&self) -> u32 {
+        self.game_version
+    }
+
+    /// written to each save. saves from a newer game version fail to load
+    pub fn set_game_version(&mut self, version: u32) {
+        self.game_version = version;
+    }
+
+    /// 0 for types without a registered version
+    pub fn schema_version(&self, type_name: &str) -> u32 {
+        self.versions.get(type_name).copied().unwrap_or(0)
+    }
+
+    /// set the current version of a type without giving a migration, for
+    /// changes that deserialize as is (e.g. a field added with serde default).
+    /// the steps from the previous current version up to this one load as is
+    /// unless a migration is added for them
+    pub fn set_schema_version(&mut self, type_name: &'static str, version: u32) {
+        let previous = self.schema_version(type_name);
+        let steps = self.migrations.entry(type_name).or_default();
+        let migrated_to = steps
+            .iter()
+            .rev()
+            .find(|(_, migration)| migration.is_some())
+            .map_or(0, |(from, _)| from + 1);
+        assert!(
+            version >= migrated_to,
+            "schema version of {} set below its migrations: {}",
+            type_name,
+            version
+        );
+        steps.retain(|from, _| *from < version);
+        for from in previous..version {
+            steps.entry(from).or_insert(None);
+        }
+        self.versions.insert(type_name, version);
+    }
+
+    /// register a migration of a type from from_version to from_version + 1.
+    /// the type's schema version is raised to at least from_version + 1
+    pub fn add_migration(
+        &mut self,
+        type_name: &'static str,
+        from_version: u32,
+        migration: Migration,
+    ) {
+        let previous = self
+            .migrations
+            .entry(type_name)
+            .or_default()
+            .insert(from_version, Some(migration));
+        assert!(
+            !matches!(previous, Some(Some(_))),
+            "duplicate migration of {} from version {}",
+            type_name,
+            from_version
+        );
+        let version = self.versions.entry(type_name).or_insert(0);
+        *version = (*version).max(from_version + 1);
+    }
+
+    /// header for a save written now
+    pub fn header(&self) -> SaveHeader {
+        SaveHeader {
+            format_version: SAVE_FORMAT_VERSION,
+            engine_version: env!("CARGO_PKG_VERSION").to_owned(),
+            game_version: self.game_version,
+            schema_versions: self
+                .versions
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v))
+                .collect(),
+        }
+    }
+
+    // whether a save with this header deserializes as is
+    fn is_current(&self, header: &SaveHeader) -> bool {
+        let saved = |type_name: &str| header.schema_versions.get(type_name).copied().unwrap_or(0);
+        header.format_version == SAVE_FORMAT_VERSION
+            && header.game_version <= self.game_version
+            && header
+                .schema_versions
+                .iter()
+                .all(|(type_name, version)| *version == self.schema_version(type_name))
+            && self
+                .versions
+                .iter()
+                .all(|(type_name, version)| saved(type_name) == *version)
+    }
+
+    /// the state of a save file in any format. a save which needs no migration
+    /// is deserialized straight from its body, others through a json value
+    /// which is migrated
+    pub(crate) fn read<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, String> {
+        let (codec, body) = format::unpack(bytes)?;
+        let loaded: LoadedHeader = format::deserialize(&body, codec)?;
+        if loaded.header.is_some_and(|header| self.is_current(&header)) {
+            let envelope: LoadedEnvelope<T> = format::deserialize(&body, codec)?;
+            return Ok(envelope.state);
+        }
+        let (_header, state) = self.migrate(format::deserialize_value(&body, codec)?)?;
+        serde_json::from_value(state).map_err(|e| e.to_string())
+    }
+
+    /// split a save into its header and state, with every entity migrated to
+    /// its current schema version
+    pub(crate) fn migrate(&self, save: Value) -> Result<(SaveHeader, Value), String> {
+        let (header, mut state) = split_envelope(save)?;
+        if header.format_version > SAVE_FORMAT_VERSION {
+            return Err(format!(
+                "save format version {} is newer than supported ({})",
+                header.format_version, SAVE_FORMAT_VERSION
+            ));
+        }
+        if header.game_version > self.game_version {
+            return Err(format!(
+                "save is from game version {}, newer than {}",
+                header.game_version, self.game_version
+            ));
+        }
+
+        let layers = state
+            .get_mut("persistent_layers")
+            .and_then(Value::as_object_mut)
+            .ok_or("save has no persistent layers")?;
+        for tagged in layers
+            .values_mut()
+            .filter_map(Value::as_array_mut)
+            .flatten()
+        {
+            let entity = tagged.get_mut("e").ok_or("saved entity has no body")?;
+            self.migrate_entity(&header, entity)?;
+        }
+        Ok((header, state))
+    }
+
+    fn migrate_entity(&self, header: &SaveHeader, entity: &mut Value) -> Result<(), String> {
+        let type_name = entity
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or("saved entity has no type tag")?
+            .to_owned();
+        let saved = header.schema_versions.get(&type_name).copied().unwrap_or(0);
+        let current = self.schema_version(&type_name);
+        if saved > current {
+            return Err(format!(
+                "{} was saved with schema version {}, newer than {}",
+                type_name, saved, current
+            ));
+        }
+        let steps = self.migrations.get(type_name.as_str());
+        for from in saved..current {
+            match steps.and_then(|steps| steps.get(&from)) {
+                Some(Some(migration)) => migration(entity).map_err(|e| {
+                    format!("migration of {} from version {}: {}", type_name, from, e)
+                })?,
+                Some(None) => (),
+                None => {
+                    return Err(format!(
+                        "{} was saved with schema version {}, but there is no migration from version {}",
+                        type_name, saved, from
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// saves from before versioning are only the state, and get a default header
+fn split_envelope(save: Value) -> Result<(SaveHeader, Value), String> {
+    match save {
+        Value::Object(mut map) if map.contains_key("header") => {
+            let header =
+                serde_json::from_value(map.remove("header").unwrap()).map_err(|e| e.to_string())?;
+            let state = map
+                .remove("state")
+                .ok_or("save has a header but no state")?;
+            Ok((header, state))
+        }
+        state => Ok((SaveHeader::default(), state)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn add_health(e: &mut Value) -> Result<(), String> {
+        e["health"] = 100.into();
+        Ok(())
+    }
+
+    fn rename_hp(e: &mut Value) -> Result<(), String> {
+        let health = e["health"].take();
+        e.as_object_mut().unwrap().remove("health");
+        e["hp"] = health;
+        Ok(())
+    }
+
+    fn fail(_: &mut Value) -> Result<(), String> {
+        Err("can't".to_owned())
+    }
+
+    // the same shape as GameState writes, with a single entity
+    fn save(schema_versions: &[(&str, u32)], entity: Value) -> Value {
+        json!({
+            "header": {
+                "format_version": SAVE_FORMAT_VERSION,
+                "engine_version": "0.0.0",
+                "game_version": 0,
+                "schema_versions": schema_versions
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), *v))
+                    .collect::<BTreeMap<_, _>>(),
+            },
+            "state": {
+                "persistent_layers": {"objects": [{"e": entity, "tag": 0, "refs": []}]},
+                "tick": 0,
+                "simulated_time": {"secs": 0, "nanos": 0},
+                "rng": {"seed": vec![0; 32], "stream": 0, "word_pos": [0, 0]},
+                "next_id": 1,
+            },
+        })
+    }
+
+    fn migrated_entity(schema: &SaveSchema, save: Value) -> Result<Value, String> {
+        let (_, mut state) = schema.migrate(save)?;
+        Ok(state["persistent_layers"]["objects"][0]["e"].take())
+    }
+
+    #[test]
+    fn migrations_run_in_order_from_saved_version() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 0, add_health);
+        schema.add_migration("Player", 1, rename_hp);
+        assert_eq!(schema.schema_version("Player"), 2);
+
+        let old = save(&[], json!({"type": "Player", "x": 1}));
+        let e = migrated_entity(&schema, old).unwrap();
+        assert_eq!(e, json!({"type": "Player", "x": 1, "hp": 100}));
+
+        let newer = save(&[("Player", 1)], json!({"type": "Player", "health": 5}));
+        let e = migrated_entity(&schema, newer).unwrap();
+        assert_eq!(e, json!({"type": "Player", "hp": 5}));
+
+        let current = save(&[("Player", 2)], json!({"type": "Player", "hp": 5}));
+        let e = migrated_entity(&schema, current).unwrap();
+        assert_eq!(e, json!({"type": "Player", "hp": 5}));
+    }
+
+    #[test]
+    fn missing_migration_step_is_an_error() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 1, rename_hp);
+        assert_eq!(schema.schema_version("Player"), 2);
+
+        let from_one = save(&[("Player", 1)], json!({"type": "Player", "health": 5}));
+        assert!(migrated_entity(&schema, from_one).is_ok());
+        let from_zero = save(&[], json!({"type": "Player"}));
+        let e = migrated_entity(&schema, from_zero).unwrap_err();
+        assert!(e.contains("no migration from version 0"), "{}", e);
+    }
+
+    #[test]
+    fn set_schema_version_steps_load_as_is() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 0, add_health);
+        schema.set_schema_version("Player", 3);
+        let old = save(&[], json!({"type": "Player"}));
+        let e = migrated_entity(&schema, old).unwrap();
+        assert_eq!(e, json!({"type": "Player", "health": 100}));
+
+        // a migration can later be given for a step that loaded as is
+        schema.add_migration("Player", 1, rename_hp);
+        let old = save(&[], json!({"type": "Player"}));
+        let e = migrated_entity(&schema, old).unwrap();
+        assert_eq!(e, json!({"type": "Player", "hp": 100}));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate migration")]
+    fn duplicate_migration_panics() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 0, add_health);
+        schema.add_migration("Player", 0, rename_hp);
+    }
+
+    #[test]
+    #[should_panic(expected = "set below its migrations")]
+    fn schema_version_below_migrations_panics() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 1, rename_hp);
+        schema.set_schema_version("Player", 1);
+    }
+
+    #[test]
+    fn failing_migration_is_an_error() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 0, fail);
+        let e = migrated_entity(&schema, save(&[], json!({"type": "Player"}))).unwrap_err();
+        assert!(e.contains("can't"), "{}", e);
+    }
+
+    #[test]
+    fn newer_saves_are_rejected() {
+        let mut schema = SaveSchema::default();
+        schema.set_schema_version("Player", 1);
+        let newer_type = save(&[("Player", 2)], json!({"type": "Player"}));
+        assert!(migrated_entity(&schema, newer_type).is_err());
+
+        let mut newer_game = save(&[], json!({"type": "Player"}));
+        newer_game["header"]["game_version"] = 1.into();
+        assert!(migrated_entity(&schema, newer_game).is_err());
+
+        let mut newer_format = save(&[], json!({"type": "Player"}));
+        newer_format["header"]["format_version"] = (SAVE_FORMAT_VERSION + 1).into();
+        assert!(migrated_entity(&schema, newer_format).is_err());
+    }
+
+    #[test]
+    fn saves_without_a_header_migrate_from_version_zero() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 0, add_health);
+        let legacy = json!({
+            "persistent_layers": {"objects": [{"e": {"type": "Player"}, "tag": 0, "refs": []}]},
+            "tick": 0,
+        });
+        let (header, state) = schema.migrate(legacy).unwrap();
+        assert_eq!(header, SaveHeader::default());
+        assert_eq!(
+            state["persistent_layers"]["objects"][0]["e"],
+            json!({"type": "Player", "health": 100})
+        );
+    }
+
+    #[test]
+    fn header_lists_current_versions() {
+        let mut schema = SaveSchema::default();
+        schema.set_game_version(7);
+        schema.add_migration("Player", 0, add_health);
+        let header = schema.header();
+        assert_eq!(header.format_version, SAVE_FORMAT_VERSION);
+        assert_eq!(header.game_version, 7);
+        assert_eq!(header.schema_versions.get("Player"), Some(&1));
+    }
+
+    #[test]
+    fn only_older_headers_need_migration() {
+        let mut schema = SaveSchema::default();
+        schema.set_game_version(3);
+        schema.add_migration("Player", 0, add_health);
+        let mut header = schema.header();
+        assert!(schema.is_current(&header));
+        header.game_version = 2;
+        assert!(schema.is_current(&header));
+
+        let newer_game = SaveHeader {
+            game_version: 4,
+            ..header.clone()
+        };
+        let older_format = SaveHeader {
+            format_version: 0,
+            ..header.clone()
+        };
+        let older_type = SaveHeader {
+            schema_versions: BTreeMap::new(),
+            ..header.clone()
+        };
+        let unknown_type = SaveHeader {
+            schema_versions: BTreeMap::from([("Player".to_owned(), 1), ("Enemy".to_owned(), 1)]),
+            ..header.clone()
+        };
+        for header in [newer_game, older_format, older_type, unknown_type] {
+            assert!(!schema.is_current(&header), "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn read_migrates_only_older_saves() {
+        let mut schema = SaveSchema::default();
+        schema.add_migration("Player", 0, add_health);
+        let current = save(&[("Player", 1)], json!({"type": "Player", "health": 5}));
+        let older = save(&[], json!({"type": "Player"}));
+        for format in [format::SaveFormat::JSON, format::SaveFormat::COMPACT] {
+            for (save, health) in [(&current, 5), (&older, 100)] {
+                let bytes = format::encode(save, format, &Default::default()).unwrap();
+                let state: Value = schema.read(&bytes).unwrap();
+                assert_eq!(
+                    state["persistent_layers"]["objects"][0]["e"]["health"],
+                    health
+                );
+            }
+        }
+
+        // json from before the container and the header
+        let legacy = serde_json::to_vec(&older["state"]).unwrap();
+        let state: Value = schema.read(&legacy).unwrap();
+        assert_eq!(state["persistent_layers"]["objects"][0]["e"]["health"], 100);
+
+        let newer = save(&[("Player", 2)], json!({"type": "Player"}));
+        let bytes =
+            format::encode(&newer, format::SaveFormat::COMPACT, &Default::default()).unwrap();
+        assert!(schema.read::<Value>(&bytes).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    fs,
+    io::Seek,
+    path::{Path, PathBuf},
+};
+
+use sdl2::{
+    image::{ImageRWops, SaveSurface},
+    pixels::PixelFormatEnum,
+    rwops::RWops,
+    surface::{Surface, SurfaceRef},
+};
+
+use super::{backup_path, format};
+
+/// describes a save, without its entities. stored at the start of the save
+/// file so that a list of saves can be shown quickly
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SaveMetadata {
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    /// simulated time of the game when saved
+    pub play_time: std::time::Duration,
+    /// defined by the game, e.g. the level and character name
+    pub summary: String,
+    /// png. see set_thumbnail
+    #[serde(skip)]
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+impl SaveMetadata {
+    /// timestamped now
+    pub fn new(play_time: std::time::Duration, summary: String) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self {
+            timestamp,
+            play_time,
+            summary,
+            thumbnail: None,
+        }
+    }
+
+    /// a frame (e.g. from `GameState::screenshot`) scaled down to this width,
+    /// keeping its aspect ratio
+    pub fn set_thumbnail(&mut self, frame: &SurfaceRef, width: u32) -> Result<(), String> {
+        let height = (frame.height() as u64 * width as u64 / frame.width().max(1) as u64).max(1);
+        let mut source = frame.convert_format(PixelFormatEnum::RGBA32)?;
+        source.set_blend_mode(sdl2::render::BlendMode::None)?;
+        let mut scaled = Surface::new(width, height as u32, PixelFormatEnum::RGBA32)?;
+        source.blit_scaled(None, &mut scaled, None)?;
+
+        // uncompressed size, plus room for png's overhead
+        let raw = (width as u64 * height * 4) as usize;
+        let mut buffer = vec![0u8; raw + raw / 8 + 4096];
+        let written = {
+            let mut rw = RWops::from_bytes_mut(&mut buffer)?;
+            scaled.save_rw(&mut rw)?;
+            rw.stream_position().map_err(|e| e.to_string())?
+        };
+        buffer.truncate(written as usize);
+        self.thumbnail = Some(buffer);
+        Ok(())
+    }
+
+    /// the decoded thumbnail, if there is one
+    pub fn thumbnail_surface(&self) -> Result<Option<Surface<'static>>, String> {
+        match &self.thumbnail {
+            Some(png) => RWops::from_bytes(png)?.load_png().map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// a slot in a list of saves
+#[derive(Clone, Debug)]
+pub struct SlotInfo {
+    pub name: String,
+    /// None if the save has no metadata (it's from before slots). an error if
+    /// it can't be read, e.g. the file is corrupt
+    pub metadata: Result<Option<SaveMetadata>, String>,
+}
+
+/// named saves in a directory. see `GameState::save_slot` and
+/// `GameState::load_slot`.\
+/// each slot is a file, name.sav, along with its backups
+#[derive(Clone, Debug)]
+pub struct SaveSlots {
+    dir: PathBuf,
+}
+
+impl SaveSlots {
+    const EXTENSION: &'static str = "sav";
+
+    /// of thumbnails given to `GameState::save_slot`
+    pub const THUMBNAIL_WIDTH: u32 = 160;
+
+    /// the saves directory for the game under the user's local data directory,
+    /// e.g. ~/.local/share/my_game/saves. it's created if it doesn't exist
+    pub fn new(game_name: &str) -> Result<Self, String> {
+        let mut dir = dirs::data_local_dir().ok_or("no local data directory")?;
+        dir.push(game_name.to_lowercase().replace(' ', "_"));
+        dir.push("saves");
+        Self::in_dir(dir)
+    }
+
+    /// any directory. it's created if it doesn't exist
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Result<Self, String> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// path of a slot's save file. errors if the name isn't usable as a file
+    /// name on every platform (see `is_valid_name`)
+    pub fn path(&self, slot: &str) -> Result<PathBuf, String> {
+        if !Self::is_valid_name(slot) {
+            return Err(format!("invalid save slot name: {:?}", slot));
+        }
+        Ok(self.dir.join(format!("{}.{}", slot, Self::EXTENSION)))
+    }
+
+    /// not empty, without a leading dot, trailing dot or space, control
+    /// characters or any of \\ / : * ? " < > |, and not a name reserved by
+    /// windows (e.g. CON, NUL, COM1 or LPT1, in any case)
+    pub fn is_valid_name(slot: &str) -> bool {
+        const RESERVED: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+        // windows ignores everything from the first dot, and trailing spaces
+        let stem = slot.split('.').next().unwrap_or_default().trim_end();
+        let reserved = RESERVED.iter().any(|r| stem.eq_ignore_ascii_case(r))
+            || (stem.len() == 4
+                && ["COM", "LPT"]
+                    .iter()
+                    .any(|r| stem.as_bytes()[..3].eq_ignore_ascii_case(r.as_bytes()))
+                && matches!(stem.as_bytes()[3], b'1'..=b'9'));
+        !slot.is_empty()
+            && !slot.starts_with('.')
+            && !slot.ends_with(['.', ' '])
+            && !slot.contains(|c: char| c.is_control() || "\\/:*?\"<>|".contains(c))
+            && !reserved
+    }
+
+    pub fn exists(&self, slot: &str) -> bool {
+        self.path(slot).is_ok_and(|path| path.exists())
+    }
+
+    /// reads only the start of the file
+    pub fn metadata(&self, slot: &str) -> Result<Option<SaveMetadata>, String> {
+        let path = self.path(slot)?;
+        let file = fs::File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        format::read_metadata(&mut std::io::BufReader::new(file))
+    }
+
+    /// every slot, most recently saved first. slots without metadata, or
+    /// whose metadata can't be read, are last, by name
+    pub fn list(&self) -> Result<Vec<SlotInfo>, String> {
+        let mut slots = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().is_none_or(|e| e != Self::EXTENSION) {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let metadata = self.metadata(&name);
+            slots.push(SlotInfo { name, metadata });
+        }
+        slots.sort_by(|a, b| match (&a.metadata, &b.metadata) {
+            (Ok(Some(a)), Ok(Some(b))) => b.timestamp.cmp(&a.timestamp),
+            (Ok(Some(_)), _) => std::cmp::Ordering::Less,
+            (_, Ok(Some(_))) => std::cmp::Ordering::Greater,
+            _ => a.name.cmp(&b.name),
+        });
+        Ok(slots)
+    }
+
+    // the slot's backups which exist, in order. the directory is listed
+    // rather than probing bak1, bak2, ..., since there can be gaps (e.g. a
+    // backup deleted by hand) and saves may have kept more than now
+    fn backups(&self, slot: &str) -> Result<Vec<(usize, PathBuf)>, String> {
+        let path = self.path(slot)?;
+        let prefix = format!("{}.{}.bak", slot, Self::EXTENSION);
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(|e| e.to_string())? {
+            let name = entry.map_err(|e| e.to_string())?.file_name();
+            let n = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|n| {
+                    n.parse::<usize>()
+                        .ok()
+                        .filter(|i| *i > 0 && i.to_string() == n)
+                });
+            if let Some(n) = n {
+                backups.push((n, backup_path(&path, n)));
+            }
+        }
+        backups.sort();
+        Ok(backups)
+    }
+
+    /// the slot and its backups
+    pub fn delete(&self, slot: &str) -> Result<(), String> {
+        let path = self.path(slot)?;
+        for (_, backup) in self.backups(slot)? {
+            fs::remove_file(&backup).map_err(|e| format!("{}: {}", backup.display(), e))?;
+        }
+        fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// along with its backups. errors if the new slot, or a backup of it,
+    /// exists
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        let (from_path, to_path) = (self.path(from)?, self.path(to)?);
+        if to_path.exists() || !self.backups(to)?.is_empty() {
+            return Err(format!("save slot already exists: {}", to));
+        }
+        fs::rename(&from_path, &to_path).map_err(|e| format!("{}: {}", from_path.display(), e))?;
+        for (n, backup) in self.backups(from)? {
+            fs::rename(&backup, backup_path(&to_path, n))
+                .map_err(|e| format!("{}: {}", backup.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// only the current save is copied, not its backups. errors if the new
+    /// slot exists
+    pub fn copy(&self, from: &str, to: &str) -> Result<(), String> {
+        let (from_path, to_path) = (self.path(from)?, self.path(to)?);
+        if to_path.exists() {
+            return Err(format!("save slot already exists: {}", to));
+        }
+        fs::copy(&from_path, &to_path)
+            .map(|_| ())
+            .map_err(|e| format!("{}: {}", from_path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::{write_atomic, SaveFormat};
+
+    fn write(slots: &SaveSlots, slot: &str, timestamp: u64) {
+        let metadata = SaveMetadata {
+            timestamp,
+            ..Default::default()
+        };
+        let bytes = format::encode(&timestamp, SaveFormat::COMPACT, &metadata).unwrap();
+        write_atomic(&slots.path(slot).unwrap(), &bytes, 2).unwrap();
+    }
+
+    fn timestamp(slots: &SaveSlots, slot: &str) -> u64 {
+        slots.metadata(slot).unwrap().unwrap().timestamp
+    }
+
+    #[test]
+    fn names() {
+        for valid in [
+            "a",
+            "slot 1",
+            "autosave_1",
+            "a.b",
+            "console",
+            "com0",
+            "lpt10",
+            "a\u{20ac}",
+        ] {
+            assert!(SaveSlots::is_valid_name(valid), "{:?}", valid);
+        }
+        for invalid in [
+            "", ".hidden", "a.", "a ", "a/b", "a\\b", "c:", "a*", "a?", "\"a\"", "<a>", "a|b",
+            "a\nb", "a\0", "CON", "nul", "Aux.txt", "prn ", "COM1", "lpt9",
+        ] {
+            assert!(!SaveSlots::is_valid_name(invalid), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn list_is_newest_first_then_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let slots = SaveSlots::in_dir(dir.path()).unwrap();
+        write(&slots, "old", 1);
+        write(&slots, "new", 3);
+        write(&slots, "middle", 2);
+        // from before slots, and corrupt
+        fs::write(slots.path("legacy").unwrap(), b"{}").unwrap();
+        fs::write(slots.path("broken").unwrap(), b"GSAV").unwrap();
+        // not slots
+        fs::write(dir.path().join("notes.txt"), b"").unwrap();
+        write(&slots, "old", 4);
+        write(&slots, "old", 1);
+
+        let list = slots.list().unwrap();
+        let names: Vec<&str> = list.iter().map(|slot| slot.name.as_str()).collect();
+        assert_eq!(names, ["new", "middle", "old", "broken", "legacy"]);
+        assert!(list[3].metadata.is_err());
+        assert_eq!(list[4].metadata, Ok(None));
+    }
+
+    #[test]
+    fn rename_moves_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let slots = SaveSlots::in_dir(dir.path()).unwrap();
+        for t in 1..=3 {
+            write(&slots, "a", t);
+        }
+        slots.rename("a", "b").unwrap();
+        assert!(!slots.exists("a"));
+        assert!(slots.backups("a").unwrap().is_empty());
+        assert_eq!(timestamp(&slots, "b"), 3);
+        let backups = slots.backups("b").unwrap();
+        assert_eq!(backups.len(), 2);
+        let path = slots.path("b").unwrap();
+        assert_eq!(backups[1], (2, backup_path(&path, 2)));
+
+        // onto an existing slot, or the leftover backups of a deleted one
+        write(&slots, "c", 1);
+        assert!(slots.rename("b", "c").is_err());
+        write(&slots, "d", 1);
+        write(&slots, "d", 2);
+        fs::remove_file(slots.path("d").unwrap()).unwrap();
+        assert!(slots.rename("b", "d").is_err());
+        assert_eq!(timestamp(&slots, "b"), 3);
+    }
+
+    #[test]
+    fn delete_removes_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let slots = SaveSlots::in_dir(dir.path()).unwrap();
+        for t in 1..=3 {
+            write(&slots, "a", t);
+        }
+        slots.copy("a", "b").unwrap();
+        slots.delete("a").unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert_eq!(timestamp(&slots, "b"), 3);
+        assert!(slots.backups("b").unwrap().is_empty());
+    }
+
+    #[test]
+    fn backups_after_a_gap() {
+        let dir = tempfile::tempdir().unwrap();
+        let slots = SaveSlots::in_dir(dir.path()).unwrap();
+        let path = slots.path("a").unwrap();
+        for t in 1..=3 {
+            write(&slots, "a", t);
+        }
+        fs::remove_file(backup_path(&path, 1)).unwrap();
+        // from when more backups were kept
+        fs::write(backup_path(&path, 12), b"").unwrap();
+        // not backups of a
+        fs::write(backup_path(&slots.path("ab").unwrap(), 1), b"").unwrap();
+        fs::write(dir.path().join("a.sav.bak0"), b"").unwrap();
+        fs::write(dir.path().join("a.sav.bak02"), b"").unwrap();
+        fs::write(dir.path().join("a.sav.bakx"), b"").unwrap();
+
+        let backups = slots.backups("a").unwrap();
+        assert_eq!(
+            backups,
+            [(2, backup_path(&path, 2)), (12, backup_path(&path, 12))]
+        );
+        slots.rename("a", "b").unwrap();
+        assert!(slots.backups("a").unwrap().is_empty());
+        assert_eq!(slots.backups("b").unwrap().len(), 2);
+        slots.delete("b").unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+    }
+}
@@ -0,0 +1,327 @@
+//! golden image testing. render a frame offscreen, then compare it against a
+//! png that was checked in when the frame was known to be right:
+//!
+//! ```no_run
+//! # use game_engine::{core::GameState, snapshot::{self, Tolerance}};
+//! # fn main() -> Result<(), String> {
+//! # let state = GameState::new_headless((320, 240), &["world"])?;
+//! let frame = snapshot::render_state(&state, (320, 240), 0f32)?;
+//! snapshot::assert_matches_golden(&frame, "tests/golden/hello.png", Tolerance::default())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! a missing golden is a failure. set the UPDATE_GOLDEN environment variable to
+//! write the current frames as the new golden images instead of comparing
+
+use std::path::{Path, PathBuf};
+
+use sdl2::{
+    image::{LoadSurface, SaveSurface},
+    pixels::{Color, PixelFormatEnum},
+    surface::{Surface, SurfaceRef},
+};
+
+use crate::{
+    core::GameState,
+    render::{Renderer, SurfaceRenderer},
+    ui::UI,
+};
+
+/// environment variable which makes assert_matches_golden write goldens
+pub const UPDATE_GOLDEN_VAR: &str = "UPDATE_GOLDEN";
+
+/// draw with f onto a blank (black) offscreen target of this size
+pub fn render_with<F>(size: (u32, u32), f: F) -> Result<Surface<'static>, String>
+where
+    F: FnOnce(&mut dyn Renderer) -> Result<(), String>,
+{
+    let mut renderer = SurfaceRenderer::new_offscreen(size)?;
+    renderer.set_draw_color(Color::BLACK);
+    renderer.clear();
+    f(&mut renderer)?;
+    Ok(renderer.canvas.into_surface())
+}
+
+/// a frame of the game state's entities, as render would draw it without the
+/// post render hook
+pub fn render_state(
+    state: &GameState,
+    size: (u32, u32),
+    alpha: f32,
+) -> Result<Surface<'static>, String> {
+    render_with(size, |renderer| state.render_to(renderer, alpha))
+}
+
+/// the ui's layers. the ui should already be sized to match (see
+/// `UI::resize`)
+pub fn render_ui(ui: &UI, size: (u32, u32)) -> Result<Surface<'static>, String> {
+    render_with(size, |renderer| ui.render(renderer))
+}
+
+/// saved as 32 bit RGBA, whatever the surface's format
+pub fn save_png(surface: &SurfaceRef, path: impl AsRef<Path>) -> Result<(), String> {
+    surface.convert_format(PixelFormatEnum::RGBA32)?.save(path)
+}
+
+pub fn load_png(path: impl AsRef<Path>) -> Result<Surface<'static>, String> {
+    Surface::from_file(path)
+}
+
+/// how different an image can be from its golden and still match
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    /// a pixel differs if any of its channels differ by more than this
+    pub channel: u8,
+    /// the number of pixels which can differ
+    pub pixels: usize,
+}
+
+impl Default for Tolerance {
+    /// allows for slight rounding differences in blending
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            pixels: 0,
+        }
+    }
+}
+
+/// result of compare
+pub struct Comparison {
+    /// pixels with a channel that differs by more than the tolerance
+    pub differing_pixels: usize,
+    /// largest difference in any channel
+    pub max_difference: u8,
+    /// differing pixels in red, over a faded copy of the expected image
+    pub diff: Surface<'static>,
+}
+
+fn rgba_pixels(surface: &SurfaceRef) -> Result<Vec<[u8; 4]>, String> {
+    let converted = surface.convert_format(PixelFormatEnum::RGBA32)?;
+    let (w, h) = converted.size();
+    let pitch = converted.pitch() as usize;
+    Ok(converted.with_lock(|bytes| {
+        let mut pixels = Vec::with_capacity((w * h) as usize);
+        for y in 0..h as usize {
+            let row = &bytes[y * pitch..y * pitch + w as usize * 4];
+            for p in row.chunks_exact(4) {
+                pixels.push([p[0], p[1], p[2], p[3]]);
+            }
+        }
+        pixels
+    }))
+}
+
+/// pixel by pixel comparison. errors if the sizes differ
+pub fn compare(
+    actual: &SurfaceRef,
+    expected: &SurfaceRef,
+    channel_tolerance: u8,
+) -> Result<Comparison, String> {
+    if actual.size() != expected.size() {
+        return Err(format!(
+            "image size {:?} doesn't match expected {:?}",
+            actual.size(),
+            expected.size()
+        ));
+    }
+    let (w, h) = expected.size();
+    let actual_pixels = rgba_pixels(actual)?;
+    let expected_pixels = rgba_pixels(expected)?;
+
+    let mut diff = Surface::new(w, h, PixelFormatEnum::RGBA32)?;
+    let pitch = diff.pitch() as usize;
+    let mut differing_pixels = 0;
+    let mut max_difference = 0u8;
+    diff.with_lock_mut(|bytes| {
+        for (i, (a, e)) in actual_pixels.iter().zip(expected_pixels.iter()).enumerate() {
+            let difference = a
+                .iter()
+                .zip(e.iter())
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap();
+            max_difference = max_difference.max(difference);
+            let out = if difference > channel_tolerance {
+                differing_pixels += 1;
+                [255, 0, 0, 255]
+            } else {
+                let grey = ((e[0] as u16 + e[1] as u16 + e[2] as u16) / 3 / 4) as u8;
+                [grey, grey, grey, 255]
+            };
+            let (x, y) = (i % w as usize, i / w as usize);
+            let offset = y * pitch + x * 4;
+            bytes[offset..offset + 4].copy_from_slice(&out);
+        }
+    });
+
+    Ok(Comparison {
+        differing_pixels,
+        max_difference,
+        diff,
+    })
+}
+
+// next to the golden, e.g. button.png -> button.diff.png
+fn sibling_path(golden: &Path, suffix: &str) -> PathBuf {
+    let stem = golden.file_stem().unwrap_or_default().to_string_lossy();
+    golden.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+/// compare a frame against the golden png at the path.\
+/// if UPDATE_GOLDEN is set, the frame is saved as the golden instead. on
+/// mismatch, or if the golden doesn't exist, the frame is saved beside the
+/// golden as name.actual.png (along with name.diff.png for a mismatch) and an
+/// error describing the failure is returned
+pub fn assert_matches_golden(
+    actual: &SurfaceRef,
+    golden: impl AsRef<Path>,
+    tolerance: Tolerance,
+) -> Result<(), String> {
+    let golden = golden.as_ref();
+    if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        if let Some(dir) = golden.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        return save_png(actual, golden);
+    }
+
+    let actual_path = sibling_path(golden, "actual");
+    if !golden.exists() {
+        if let Some(dir) = golden.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        save_png(actual, &actual_path)?;
+        return Err(format!(
+            "{}: golden doesn't exist. actual saved to {}; set {} to accept it",
+            golden.display(),
+            actual_path.display(),
+            UPDATE_GOLDEN_VAR
+        ));
+    }
+
+    let expected = load_png(golden)?;
+    let comparison = match compare(actual, &expected, tolerance.channel) {
+        Ok(comparison) => comparison,
+        Err(e) => {
+            save_png(actual, &actual_path)?;
+            return Err(format!(
+                "{}: {}. actual saved to {}",
+                golden.display(),
+                e,
+                actual_path.display()
+            ));
+        }
+    };
+    if comparison.differing_pixels <= tolerance.pixels {
+        return Ok(());
+    }
+
+    let diff_path = sibling_path(golden, "diff");
+    save_png(actual, &actual_path)?;
+    save_png(&comparison.diff, &diff_path)?;
+    Err(format!(
+        "{}: {} pixels differ (max channel difference {}, tolerance {:?}). actual saved to {}, diff to {}",
+        golden.display(),
+        comparison.differing_pixels,
+        comparison.max_difference,
+        tolerance,
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(size: (u32, u32), color: Color) -> Surface<'static> {
+        let mut surface = Surface::new(size.0, size.1, PixelFormatEnum::RGBA32).unwrap();
+        surface.fill_rect(None, color).unwrap();
+        surface
+    }
+
+    fn set_pixel(surface: &mut Surface, x: usize, y: usize, color: [u8; 4]) {
+        let pitch = surface.pitch() as usize;
+        surface.with_lock_mut(|bytes| {
+            bytes[y * pitch + x * 4..y * pitch + x * 4 + 4].copy_from_slice(&color)
+        });
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = filled((4, 3), Color::RGB(10, 20, 30));
+        let comparison = compare(&image, &image, 0).unwrap();
+        assert_eq!(comparison.differing_pixels, 0);
+        assert_eq!(comparison.max_difference, 0);
+    }
+
+    #[test]
+    fn differences_within_channel_tolerance_match() {
+        let expected = filled((4, 3), Color::RGB(100, 100, 100));
+        let mut actual = filled((4, 3), Color::RGB(100, 100, 100));
+        set_pixel(&mut actual, 1, 2, [102, 99, 100, 255]);
+
+        let comparison = compare(&actual, &expected, 2).unwrap();
+        assert_eq!(comparison.differing_pixels, 0);
+        assert_eq!(comparison.max_difference, 2);
+
+        let comparison = compare(&actual, &expected, 1).unwrap();
+        assert_eq!(comparison.differing_pixels, 1);
+        assert_eq!(comparison.max_difference, 2);
+        // the differing pixel is marked in the diff
+        let diff = rgba_pixels(&comparison.diff).unwrap();
+        assert_eq!(diff[2 * 4 + 1], [255, 0, 0, 255]);
+        assert_ne!(diff[0], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn alpha_is_compared() {
+        let expected = filled((2, 2), Color::RGBA(0, 0, 0, 255));
+        let actual = filled((2, 2), Color::RGBA(0, 0, 0, 0));
+        let comparison = compare(&actual, &expected, 2).unwrap();
+        assert_eq!(comparison.differing_pixels, 4);
+        assert_eq!(comparison.max_difference, 255);
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        let expected = filled((4, 3), Color::BLACK);
+        let actual = filled((3, 4), Color::BLACK);
+        assert!(compare(&actual, &expected, 255).is_err());
+    }
+
+    #[test]
+    fn missing_golden_fails_without_writing_it() {
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let golden = dir.path().join("missing.png");
+        let actual = filled((2, 2), Color::WHITE);
+        assert!(assert_matches_golden(&actual, &golden, Tolerance::default()).is_err());
+        assert!(!golden.exists());
+        assert!(dir.path().join("missing.actual.png").exists());
+    }
+
+    #[test]
+    fn pixel_tolerance_allows_some_differing_pixels() {
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let golden = dir.path().join("golden.png");
+        save_png(&filled((4, 4), Color::BLACK), &golden).unwrap();
+        let mut actual = filled((4, 4), Color::BLACK);
+        set_pixel(&mut actual, 0, 0, [255, 255, 255, 255]);
+
+        let one_pixel = Tolerance {
+            channel: 0,
+            pixels: 1,
+        };
+        assert!(assert_matches_golden(&actual, &golden, one_pixel).is_ok());
+        assert!(assert_matches_golden(&actual, &golden, Tolerance::default()).is_err());
+        assert!(dir.path().join("golden.diff.png").exists());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+/// axis aligned bounding box, in world coordinates. x and y are the minimum
+/// corner. a point is a box with zero width and height
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Aabb {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Aabb {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    /// a box of size w by h centered on (cx, cy)
+    pub fn centered(cx: f32, cy: f32, w: f32, h: f32) -> Self {
+        Self::new(cx - w / 2f32, cy - h / 2f32, w, h)
+    }
+
+    pub fn point(x: f32, y: f32) -> Self {
+        Self::new(x, y, 0f32, 0f32)
+    }
+
+    pub fn center(&self) -> (f32, f32) {
+        (self.x + self.w / 2f32, self.y + self.h / 2f32)
+    }
+
+    /// inclusive of the edges
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.x <= other.x + other.w
+            && other.x <= self.x + self.w
+            && self.y <= other.y + other.h
+            && other.y <= self.y + self.h
+    }
+
+    /// inclusive of the edges
+    pub fn contains_point(&self, point: (f32, f32)) -> bool {
+        point.0 >= self.x
+            && point.0 <= self.x + self.w
+            && point.1 >= self.y
+            && point.1 <= self.y + self.h
+    }
+
+    /// squared distance from the point to the nearest point in this box. zero
+    /// if the point is inside
+    pub fn distance_squared(&self, point: (f32, f32)) -> f32 {
+        let dx = (self.x - point.0)
+            .max(0f32)
+            .max(point.0 - (self.x + self.w));
+        let dy = (self.y - point.1)
+            .max(0f32)
+            .max(point.1 - (self.y + self.h));
+        dx * dx + dy * dy
+    }
+}
+
+/// uniform grid of square cells. each value is put in every cell that its
+/// bounds overlap, so the cell size should be around the size of a typical
+/// value's bounds
+pub struct SpatialHash<T> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    /// values which span too many cells to put in each. every query checks
+    /// these
+    oversized: Vec<usize>,
+    entries: Vec<(Aabb, T)>,
+}
+
+impl<T> SpatialHash<T> {
+    /// a value overlapping more cells than this is put in oversized instead
+    const MAX_CELLS_PER_VALUE: i64 = 1024;
+
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0f32, "spatial hash cell size must be positive");
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            oversized: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.oversized.clear();
+        self.entries.clear();
+    }
+
+    // inclusive range of cells which the bounds overlap
+    fn cell_range(&self, bounds: &Aabb) -> ((i32, i32), (i32, i32)) {
+        let min = (
+            (bounds.x / self.cell_size).floor() as i32,
+            (bounds.y / self.cell_size).floor() as i32,
+        );
+        let max = (
+            ((bounds.x + bounds.w) / self.cell_size).floor() as i32,
+            ((bounds.y + bounds.h) / self.cell_size).floor() as i32,
+        );
+        (min, max)
+    }
+
+    // number of cells in an inclusive range. saturates for huge bounds
+    fn cell_count((min, max): ((i32, i32), (i32, i32))) -> i64 {
+        (max.0 as i64 - min.0 as i64 + 1).saturating_mul(max.1 as i64 - min.1 as i64 + 1)
+    }
+
+    pub fn insert(&mut self, bounds: Aabb, value: T) {
+        let index = self.entries.len();
+        let (min, max) = self.cell_range(&bounds);
+        if Self::cell_count((min, max)) > Self::MAX_CELLS_PER_VALUE {
+            self.oversized.push(index);
+            self.entries.push((bounds, value));
+            return;
+        }
+        for cx in min.0..=max.0 {
+            for cy in min.1..=max.1 {
+                self.cells.entry((cx, cy)).or_default().push(index);
+            }
+        }
+        self.entries.push((bounds, value));
+    }
+
+    /// every value whose bounds intersect the rectangle. each value appears
+    /// once, in the order they were inserted
+    pub fn query_rect(&self, rect: Aabb) -> Vec<&(Aabb, T)> {
+        let (min, max) = self.cell_range(&rect);
+        // a huge rectangle covers more cells than there are values, so
+        // checking every value is cheaper than visiting each cell
+        if Self::cell_count((min, max)) > self.entries.len() as i64 {
+            return self
+                .entries
+                .iter()
+                .filter(|(bounds, _)| bounds.intersects(&rect))
+                .collect();
+        }
+        let mut indices: Vec<usize> = self.oversized.clone();
+        for cx in min.0..=max.0 {
+            for cy in min.1..=max.1 {
+                if let Some(cell) = self.cells.get(&(cx, cy)) {
+                    indices.extend(cell.iter().copied());
+                }
+            }
+        }
+        // a value which spans several cells is found more than once
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+            .into_iter()
+            .map(|i| &self.entries[i])
+            .filter(|(bounds, _)| bounds.intersects(&rect))
+            .collect()
+    }
+
+    /// every value whose bounds are within radius of the center
+    pub fn query_radius(&self, center: (f32, f32), radius: f32) -> Vec<&(Aabb, T)> {
+        let rect = Aabb::centered(center.0, center.1, radius * 2f32, radius * 2f32);
+        let radius_squared = radius * radius;
+        self.query_rect(rect)
+            .into_iter()
+            .filter(|(bounds, _)| bounds.distance_squared(center) <= radius_squared)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values<T: Copy>(found: Vec<&(Aabb, T)>) -> Vec<T> {
+        found.into_iter().map(|(_, v)| *v).collect()
+    }
+
+    #[test]
+    fn query_rect_finds_intersecting_once_in_insertion_order() {
+        let mut hash = SpatialHash::new(10f32);
+        hash.insert(Aabb::new(0f32, 0f32, 5f32, 5f32), 0);
+        // spans four cells
+        hash.insert(Aabb::new(5f32, 5f32, 10f32, 10f32), 1);
+        hash.insert(Aabb::new(100f32, 100f32, 5f32, 5f32), 2);
+        hash.insert(Aabb::new(-20f32, -20f32, 5f32, 5f32), 3);
+
+        let found = values(hash.query_rect(Aabb::new(0f32, 0f32, 20f32, 20f32)));
+        assert_eq!(found, vec![0, 1]);
+        let found = values(hash.query_rect(Aabb::new(-30f32, -30f32, 12f32, 12f32)));
+        assert_eq!(found, vec![3]);
+        assert!(hash
+            .query_rect(Aabb::new(50f32, 50f32, 1f32, 1f32))
+            .is_empty());
+    }
+
+    #[test]
+    fn query_rect_is_inclusive_of_edges() {
+        let mut hash = SpatialHash::new(10f32);
+        hash.insert(Aabb::new(0f32, 0f32, 10f32, 10f32), 0);
+        assert_eq!(values(hash.query_rect(Aabb::point(10f32, 10f32))), vec![0]);
+        assert_eq!(values(hash.query_rect(Aabb::point(0f32, 0f32))), vec![0]);
+    }
+
+    #[test]
+    fn huge_query_rect_checks_every_value() {
+        let mut hash = SpatialHash::new(1f32);
+        hash.insert(Aabb::new(0f32, 0f32, 1f32, 1f32), 0);
+        hash.insert(Aabb::new(-1e6, 3e5, 1f32, 1f32), 1);
+        let everything = Aabb::new(-1e30, -1e30, 2e30, 2e30);
+        assert_eq!(values(hash.query_rect(everything)), vec![0, 1]);
+        let wide = Aabb::new(-1e30, 0f32, 2e30, 0.5);
+        assert_eq!(values(hash.query_rect(wide)), vec![0]);
+    }
+
+    #[test]
+    fn huge_value_is_found_by_small_queries() {
+        let mut hash = SpatialHash::new(1f32);
+        hash.insert(Aabb::new(-1e30, -1e30, 2e30, 2e30), 0);
+        for i in 1..10 {
+            hash.insert(Aabb::point(i as f32 * 100f32, 0f32), i);
+        }
+        let found = values(hash.query_rect(Aabb::new(99f32, -1f32, 2f32, 2f32)));
+        assert_eq!(found, vec![0, 1]);
+    }
+
+    #[test]
+    fn query_radius_uses_distance_to_bounds() {
+        let mut hash = SpatialHash::new(10f32);
+        hash.insert(Aabb::new(10f32, 0f32, 5f32, 5f32), 0);
+        // within the square around the circle, but not the circle
+        hash.insert(Aabb::new(8f32, 8f32, 5f32, 5f32), 1);
+        assert_eq!(values(hash.query_radius((0f32, 0f32), 10f32)), vec![0]);
+        assert_eq!(values(hash.query_radius((0f32, 0f32), 12f32)), vec![0, 1]);
+    }
+
+    #[test]
+    fn clear_removes_everything() {
+        let mut hash = SpatialHash::new(10f32);
+        hash.insert(Aabb::point(1f32, 1f32), 0);
+        hash.clear();
+        assert!(hash.is_empty());
+        assert!(hash
+            .query_rect(Aabb::new(0f32, 0f32, 5f32, 5f32))
+            .is_empty());
+    }
+}
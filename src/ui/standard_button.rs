@@ -1,4 +1,6 @@
-use sdl2::{pixels::Color, rect::Rect, render::WindowCanvas};
+use sdl2::{pixels::Color, rect::Rect};
+
+use crate::render::Renderer;
 
 use super::{standard_button_content::Content, Button, EventHandleResult, UIComponent, UIState};
 
@@ -72,7 +74,7 @@ impl<'sdl> UIComponent<'sdl> for StandardButton<'sdl> {
         Button::<'sdl>::process(self, ui_state, e)
     }
 
-    fn render(&self, canvas: &mut WindowCanvas) {
+    fn render(&self, renderer: &mut dyn Renderer) -> Result<(), String> {
         let bg_color = match self.focus_state {
             FocusState::Idle => self.bg_idle_color,
             FocusState::Hovered => {
@@ -81,27 +83,22 @@ impl<'sdl> UIComponent<'sdl> for StandardButton<'sdl> {
             FocusState::Pressed => self.bg_pressed_color,
         };
 
-        canvas.set_draw_color(bg_color);
+        renderer.set_draw_color(bg_color);
 
-        self.content.render(canvas, self.content_bound);
+        self.content.render(renderer, self.content_bound)?;
 
-        canvas.fill_rect(self.bound).unwrap();
+        renderer.fill_rect(self.bound)?;
         super::util::render_gradient_border(
-            canvas,
+            renderer,
             self.bound,
             self.border_outer_color,
             self.border_inner_color,
             self.border_width,
             self.border_steps,
-        );
+        )
     }
 
-    fn resize(
-        &mut self,
-        window_size: (u32, u32),
-        texture_creator: &'sdl sdl2::render::TextureCreator<sdl2::video::WindowContext>,
-        font_cache: &mut super::FontCache,
-    ) {
+    fn resize(&mut self, window_size: (u32, u32), font_cache: &mut super::FontCache) {
         // how big will the entire button be
         let bound = self.content.get_button_bound(window_size);
         let center = (bound.x + bound.w / 2, bound.y + bound.h / 2);
@@ -116,11 +113,10 @@ impl<'sdl> UIComponent<'sdl> for StandardButton<'sdl> {
             .checked_sub(u32::from(self.border_width) * 2)
             .unwrap_or(bound.height());
 
-        // have the content re-render its texture based on the space available
+        // have the content re-render its surface based on the space available
         // inside the button, not including the border. see how much space that took
         let responded_dim = self.content.resize(
             (requested_content_bound_w, requested_content_bound_h),
-            texture_creator,
             font_cache,
         );
 
@@ -185,3 +181,81 @@ impl<'sdl> Button<'sdl> for StandardButton<'sdl> {
         self.content.released()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{self, Tolerance};
+    use crate::ui::standard_button_content::{ContentFunctional, FitType, ImageContent};
+    use sdl2::{pixels::PixelFormatEnum, surface::Surface};
+
+    struct At(Rect);
+
+    impl<'sdl> ContentFunctional<'sdl> for At {
+        fn released(&mut self) -> EventHandleResult<'sdl> {
+            EventHandleResult::None
+        }
+
+        fn get_button_bound(&self, _window_size: (u32, u32)) -> Rect {
+            self.0
+        }
+    }
+
+    // a 16x16 image with a different color in each quarter
+    fn quarters(dir: &std::path::Path) -> String {
+        let mut image = Surface::new(16, 16, PixelFormatEnum::RGBA32).unwrap();
+        let colors = [Color::RED, Color::GREEN, Color::BLUE, Color::YELLOW];
+        for (i, color) in colors.into_iter().enumerate() {
+            let (x, y) = ((i % 2) as i32 * 8, (i / 2) as i32 * 8);
+            image.fill_rect(Rect::new(x, y, 8, 8), color).unwrap();
+        }
+        let path = dir.join("quarters.png");
+        snapshot::save_png(&image, &path).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn golden(name: &str) -> String {
+        format!("{}/tests/golden/{}.png", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn image_button_matches_goldens() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = ImageContent::new(
+            quarters(dir.path()),
+            Box::new(At(Rect::new(8, 8, 80, 48))),
+            FitType::Shrink,
+            0.25f32,
+        );
+        let mut button = StandardButton::default_look(Box::new(content));
+        let ttf_context = sdl2::ttf::init().unwrap();
+        let mut font_cache = super::super::FontCache::new(1, &ttf_context);
+        button.resize((96, 64), &mut font_cache);
+
+        let frame = snapshot::render_with((96, 64), |r| button.render(r)).unwrap();
+        snapshot::assert_matches_golden(
+            &frame,
+            golden("standard_button_idle"),
+            Tolerance::default(),
+        )
+        .unwrap();
+
+        Button::moved_in(&mut button);
+        let frame = snapshot::render_with((96, 64), |r| button.render(r)).unwrap();
+        snapshot::assert_matches_golden(
+            &frame,
+            golden("standard_button_hovered"),
+            Tolerance::default(),
+        )
+        .unwrap();
+
+        Button::pressed(&mut button);
+        let frame = snapshot::render_with((96, 64), |r| button.render(r)).unwrap();
+        snapshot::assert_matches_golden(
+            &frame,
+            golden("standard_button_pressed"),
+            Tolerance::default(),
+        )
+        .unwrap();
+    }
+}
@@ -1,10 +1,6 @@
-use sdl2::{
-    image::LoadTexture,
-    pixels::Color,
-    rect::Rect,
-    render::{Texture, TextureCreator, WindowCanvas},
-    video::WindowContext,
-};
+use sdl2::{image::LoadSurface, pixels::Color, rect::Rect, surface::Surface};
+
+use crate::render::{CachedSurface, Renderer};
 
 use super::{standard_button::FocusState, util::shrink_fit, EventHandleResult, FontCache};
 
@@ -22,14 +18,12 @@ pub trait ContentFunctional<'sdl> {
 ///  - what is drawn within the border of the standard button
 pub trait Content<'sdl> {
     /// returns the size of the inner content (not including the border)
-    fn resize(
-        &mut self,
-        requested_size: (u32, u32),
-        texture_creator: &'sdl TextureCreator<WindowContext>,
-        font_cache: &mut FontCache,
-    ) -> (u32, u32);
+    fn resize(&mut self, requested_size: (u32, u32), font_cache: &mut FontCache) -> (u32, u32);
 
-    fn render(&self, canvas: &mut WindowCanvas, bound: Rect);
+    /// content is kept as cached surfaces rather than textures, so that it
+    /// can be drawn by any renderer (e.g. an offscreen one) while the window
+    /// only uploads it once
+    fn render(&self, renderer: &mut dyn Renderer, bound: Rect) -> Result<(), String>;
 
     /// where does the entire button go on the screen
     fn get_button_bound(&self, window_size: (u32, u32)) -> Rect;
@@ -50,14 +44,14 @@ pub struct TextContent<'sdl> {
     // last height used to generate the font point
     height: u16,
 
-    // dimensions of texture from previous resize
+    // dimensions of surface from previous resize
     rendered_dims: (u32, u32),
-    rendered_text: Option<Texture<'sdl>>,
+    rendered_text: Option<CachedSurface>,
 
     // simple expanding the text doesn't look right, so this always renders a
     // slightly larger font for when the button is focused
     focus_rendered_dims: (u32, u32),
-    focus_rendered_text: Option<Texture<'sdl>>,
+    focus_rendered_text: Option<CachedSurface>,
 
     functional: Box<dyn ContentFunctional<'sdl> + 'sdl>,
 
@@ -88,12 +82,7 @@ impl<'sdl> TextContent<'sdl> {
 }
 
 impl<'sdl> Content<'sdl> for TextContent<'sdl> {
-    fn resize(
-        &mut self,
-        requested_size: (u32, u32),
-        texture_creator: &'sdl TextureCreator<WindowContext>,
-        font_cache: &mut FontCache,
-    ) -> (u32, u32) {
+    fn resize(&mut self, requested_size: (u32, u32), font_cache: &mut FontCache) -> (u32, u32) {
         self.height = requested_size.1.try_into().unwrap_or(u16::MAX);
         let font_rc = font_cache.get(self.font_path.clone(), self.height);
         let surface = font_rc
@@ -101,14 +90,10 @@ impl<'sdl> Content<'sdl> for TextContent<'sdl> {
             .blended(Color::RGBA(255, 255, 255, 255))
             .unwrap();
 
-        let texture = texture_creator
-            .create_texture_from_surface(&surface)
-            .unwrap();
-        let q = texture.query();
-        self.rendered_dims = (q.width, q.height);
-        self.rendered_text = Some(texture);
+        self.rendered_dims = surface.size();
+        self.rendered_text = Some(CachedSurface::new(surface));
 
-        let ret = (q.width, u32::from(self.height));
+        let ret = (self.rendered_dims.0, u32::from(self.height));
 
         // same as above but for the focused text
 
@@ -124,23 +109,19 @@ impl<'sdl> Content<'sdl> for TextContent<'sdl> {
             .blended(Color::RGBA(255, 255, 255, 255))
             .unwrap();
 
-        let texture = texture_creator
-            .create_texture_from_surface(&surface)
-            .unwrap();
-        let q = texture.query();
-        self.focus_rendered_dims = (q.width, q.height);
-        self.focus_rendered_text = Some(texture);
+        self.focus_rendered_dims = surface.size();
+        self.focus_rendered_text = Some(CachedSurface::new(surface));
 
         ret
     }
 
-    fn render(&self, canvas: &mut WindowCanvas, bound: Rect) {
+    fn render(&self, renderer: &mut dyn Renderer, bound: Rect) -> Result<(), String> {
         let dims_to_use = match self.focus_state {
             FocusState::Idle => self.rendered_dims,
             _ => self.focus_rendered_dims,
         };
 
-        let texture_to_use = match self.focus_state {
+        let surface_to_use = match self.focus_state {
             FocusState::Idle => &self.rendered_text,
             _ => &self.focus_rendered_text,
         };
@@ -162,9 +143,8 @@ impl<'sdl> Content<'sdl> for TextContent<'sdl> {
             },
         };
 
-        canvas
-            .copy(texture_to_use.as_ref().unwrap(), src_bound, dst_bound)
-            .unwrap();
+        let surface = surface_to_use.as_ref().ok_or("text content rendered before resize")?;
+        renderer.copy_cached(surface, Some(src_bound), dst_bound)
     }
 
     fn moved_in(&mut self) {
@@ -199,7 +179,7 @@ pub enum FitType {
 pub struct ImageContent<'sdl> {
     img_path: String,
     image_dims: (u32, u32),
-    rendered_image: Option<Texture<'sdl>>,
+    rendered_image: Option<CachedSurface>,
     functional: Box<dyn ContentFunctional<'sdl> + 'sdl>,
     fit_type: FitType,
     // the amount that the button zooms is when it is focused.
@@ -229,22 +209,16 @@ impl<'sdl> ImageContent<'sdl> {
 }
 
 impl<'sdl> Content<'sdl> for ImageContent<'sdl> {
-    fn resize(
-        &mut self,
-        size: (u32, u32),
-        texture_creator: &'sdl TextureCreator<WindowContext>,
-        _font_cache: &mut FontCache,
-    ) -> (u32, u32) {
+    fn resize(&mut self, size: (u32, u32), _font_cache: &mut FontCache) -> (u32, u32) {
         if let None = self.rendered_image {
-            self.rendered_image =
-                Some(texture_creator.load_texture(self.img_path.clone()).unwrap());
-            let q = self.rendered_image.as_ref().unwrap().query();
-            self.image_dims = (q.width, q.height);
+            let image = Surface::from_file(self.img_path.clone()).unwrap();
+            self.image_dims = image.size();
+            self.rendered_image = Some(CachedSurface::new(image));
         }
         size
     }
 
-    fn render(&self, canvas: &mut WindowCanvas, bound: Rect) {
+    fn render(&self, renderer: &mut dyn Renderer, bound: Rect) -> Result<(), String> {
         let bound_to_use = match self.fit_type {
             FitType::Stretch => bound,
             FitType::Shrink => shrink_fit(self.image_dims, bound),
@@ -261,13 +235,11 @@ impl<'sdl> Content<'sdl> for ImageContent<'sdl> {
             (self.image_dims.0 as f32 - border_zoom_width * 2f32) as u32,
             (self.image_dims.1 as f32 - border_zoom_width * 2f32) as u32,
         );
-        canvas
-            .copy(
-                self.rendered_image.as_ref().unwrap(),
-                src_bound,
-                bound_to_use,
-            )
-            .unwrap();
+        let image = self
+            .rendered_image
+            .as_ref()
+            .ok_or("image content rendered before resize")?;
+        renderer.copy_cached(image, Some(src_bound), bound_to_use)
     }
 
     fn moved_in(&mut self) {
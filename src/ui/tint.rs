@@ -1,5 +1,7 @@
 use sdl2::{pixels::Color, rect::Rect};
 
+use crate::render::Renderer;
+
 use super::UIComponent;
 
 /// a tint over the entire screen
@@ -16,18 +18,10 @@ impl<'sdl> UIComponent<'sdl> for Tint {
         super::EventHandleResult::None
     }
 
-    fn render(&self, canvas: &mut sdl2::render::WindowCanvas) {
-        canvas.set_draw_color(self.color);
-        canvas
-            .fill_rect(Rect::new(0, 0, u32::MAX, u32::MAX))
-            .unwrap();
+    fn render(&self, renderer: &mut dyn Renderer) -> Result<(), String> {
+        renderer.set_draw_color(self.color);
+        renderer.fill_rect(Rect::new(0, 0, u32::MAX, u32::MAX))
     }
 
-    fn resize(
-        &mut self,
-        _: (u32, u32),
-        _: &'sdl sdl2::render::TextureCreator<sdl2::video::WindowContext>,
-        _: &mut super::FontCache,
-    ) {
-    }
+    fn resize(&mut self, _: (u32, u32), _: &mut super::FontCache) {}
 }
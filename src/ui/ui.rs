@@ -2,11 +2,12 @@ use std::{num::NonZeroUsize, path::Path, rc::Rc};
 
 use lru::LruCache;
 use sdl2::{
-    render::{TextureCreator, WindowCanvas},
+    render::WindowCanvas,
     ttf::{Font, Sdl2TtfContext},
-    video::WindowContext,
 };
 
+use crate::render::Renderer;
+
 extern crate sdl2;
 
 pub enum EventHandleResult<'sdl> {
@@ -39,7 +40,7 @@ pub struct UIState {
     pub window_size: (u32, u32),
 }
 
-/// sdl is the lifetime of various borrowed structs. this includes the texture_creator and the ttf context.
+/// sdl is the lifetime of various borrowed structs. this includes the ttf context.
 /// they will be needed through the lifetime of this ui instance
 pub struct UI<'sdl> {
     // layers are rendered front to back. events are only given to the backmost
@@ -47,7 +48,6 @@ pub struct UI<'sdl> {
     // front to back
     layers: Vec<Vec<Box<dyn UIComponent<'sdl> + 'sdl>>>,
 
-    texture_creator: &'sdl TextureCreator<WindowContext>,
     font_manager: FontCache<'sdl>,
 
     /// always kept in sync with the left mouse button
@@ -55,14 +55,9 @@ pub struct UI<'sdl> {
 }
 
 impl<'sdl> UI<'sdl> {
-    pub fn new(
-        canvas: &WindowCanvas,
-        ttf_context: &'sdl Sdl2TtfContext,
-        texture_creator: &'sdl TextureCreator<WindowContext>,
-    ) -> Result<Self, String> {
+    pub fn new(canvas: &WindowCanvas, ttf_context: &'sdl Sdl2TtfContext) -> Result<Self, String> {
         Ok(Self {
             layers: Default::default(),
-            texture_creator,
             state: UIState {
                 window_size: canvas.output_size().unwrap(),
                 button_down: false,
@@ -80,11 +75,7 @@ impl<'sdl> UI<'sdl> {
         }
         // initialize resize for each component on addition
         layer.iter_mut().for_each(|component| {
-            component.resize(
-                self.state.window_size,
-                self.texture_creator,
-                &mut self.font_manager,
-            )
+            component.resize(self.state.window_size, &mut self.font_manager)
         });
 
         f(self);
@@ -123,17 +114,7 @@ impl<'sdl> UI<'sdl> {
                 // on change of window size keep self.window_size in sync and propagate
                 // it to components
                 if let sdl2::event::WindowEvent::SizeChanged(x_size, y_size) = win_event {
-                    self.state.window_size = (*x_size as u32, *y_size as u32);
-                    // propagate resize to all components
-                    self.layers.iter_mut().for_each(|layer| {
-                        layer.iter_mut().for_each(|component| {
-                            component.resize(
-                                self.state.window_size,
-                                &self.texture_creator,
-                                &mut self.font_manager,
-                            )
-                        })
-                    })
+                    self.resize((*x_size as u32, *y_size as u32));
                 }
             }
             sdl2::event::Event::MouseButtonDown {
@@ -221,10 +202,22 @@ impl<'sdl> UI<'sdl> {
         true
     }
 
-    pub fn render(&self, canvas: &mut WindowCanvas) {
-        self.layers
-            .iter()
-            .for_each(|layer| layer.iter().for_each(|component| component.render(canvas)));
+    /// resize every component to fit a window of this size. process does this
+    /// on its own when the window changes size
+    pub fn resize(&mut self, window_size: (u32, u32)) {
+        self.state.window_size = window_size;
+        self.layers.iter_mut().for_each(|layer| {
+            layer.iter_mut().for_each(|component| {
+                component.resize(self.state.window_size, &mut self.font_manager)
+            })
+        })
+    }
+
+    pub fn render(&self, renderer: &mut dyn Renderer) -> Result<(), String> {
+        for component in self.layers.iter().flatten() {
+            component.render(renderer)?;
+        }
+        Ok(())
     }
 }
 
@@ -233,17 +226,12 @@ pub trait UIComponent<'sdl> {
     fn process(&mut self, ui_state: &UIState, e: &sdl2::event::Event) -> EventHandleResult<'sdl>;
 
     /// called by UI instance
-    fn render(&self, canvas: &mut WindowCanvas);
+    fn render(&self, renderer: &mut dyn Renderer) -> Result<(), String>;
 
     /// this should only be called by UI. recalculate bounds for this component and render any graphics.\
     /// this is called when it is initially added to the ui and
     /// each time the window changes size.
-    fn resize(
-        &mut self,
-        window_size: (u32, u32),
-        texture_creator: &'sdl TextureCreator<WindowContext>,
-        font_cache: &mut FontCache,
-    );
+    fn resize(&mut self, window_size: (u32, u32), font_cache: &mut FontCache);
 
     /// a special event that happens when a ui layer is added on top of the
     /// layer that this component is a part of. this should clear any state
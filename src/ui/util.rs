@@ -1,4 +1,6 @@
-use sdl2::{pixels::Color, rect::Rect, render::WindowCanvas};
+use sdl2::{pixels::Color, rect::Rect};
+
+use crate::render::Renderer;
 
 // various render utils for the ui
 
@@ -11,13 +13,13 @@ pub fn interpolate_color(start: Color, stop: Color, progress: f32) -> Color {
 }
 
 pub fn render_gradient_border(
-    canvas: &mut WindowCanvas,
+    renderer: &mut dyn Renderer,
     bound: Rect,
     outer_color: Color,
     inner_color: Color,
     border_width: u16,
     steps: u16,
-) {
+) -> Result<(), String> {
     let step_width = border_width / (steps + 1);
     let step_width_u32 = u32::from(step_width);
     let step_width_i32 = i32::from(step_width);
@@ -28,16 +30,15 @@ pub fn render_gradient_border(
         } else {
             interpolate_color(outer_color, inner_color, i as f32 / (steps - 1) as f32)
         };
-        canvas.set_draw_color(color);
-        canvas // top
+        renderer.set_draw_color(color);
+        renderer // top
             .fill_rect(Rect::new(
                 bound.x + i32::from(i) * step_width_i32,
                 bound.y + i32::from(i) * step_width_i32,
                 bound.width() - u32::from(i) * step_width_u32 * 2,
                 step_width_u32,
-            ))
-            .unwrap();
-        canvas // right
+            ))?;
+        renderer // right
             .fill_rect(Rect::new(
                 bound.x + bound.w - (1 + i32::from(i)) * step_width_i32,
                 bound.y + i32::from(i) * step_width_i32,
@@ -46,17 +47,15 @@ pub fn render_gradient_border(
                     .height()
                     .checked_sub(u32::from(i) * step_width_u32 * 2)
                     .unwrap_or(bound.height()),
-            ))
-            .unwrap();
-        canvas // bottom
+            ))?;
+        renderer // bottom
             .fill_rect(Rect::new(
                 bound.x + i32::from(i) * step_width_i32,
                 bound.y + bound.h - (1 + i32::from(i)) * step_width_i32,
                 bound.width() - u32::from(i) * step_width_u32 * 2,
                 step_width_u32,
-            ))
-            .unwrap();
-        canvas // left
+            ))?;
+        renderer // left
             .fill_rect(Rect::new(
                 bound.x + i32::from(i) * step_width_i32,
                 bound.y + i32::from(i) * step_width_i32,
@@ -65,9 +64,9 @@ pub fn render_gradient_border(
                     .height()
                     .checked_sub(u32::from(i) * step_width_u32 * 2)
                     .unwrap_or(bound.height()),
-            ))
-            .unwrap();
+            ))?;
     }
+    Ok(())
 }
 
 /// suitable for textures with a transparent background\
@@ -90,3 +89,29 @@ pub fn shrink_fit(src_dim: (u32, u32), dist_bound: Rect) -> Rect {
         Rect::new(x + dist_bound.x, y + dist_bound.y, width, height)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{self, Tolerance};
+
+    #[test]
+    fn gradient_border_matches_golden() {
+        let frame = snapshot::render_with((40, 30), |renderer| {
+            render_gradient_border(
+                renderer,
+                Rect::new(4, 3, 32, 24),
+                Color::RGB(250, 200, 0),
+                Color::RGB(0, 50, 200),
+                8,
+                3,
+            )
+        })
+        .unwrap();
+        let golden = format!(
+            "{}/tests/golden/gradient_border.png",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        snapshot::assert_matches_golden(&frame, golden, Tolerance::default()).unwrap();
+    }
+}